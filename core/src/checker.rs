@@ -1,23 +1,44 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use anyhow::Result;
-use bbm_primitives::{FilledTransaction, Leaf, LeafId, Transaction};
+use bbm_primitives::{FilledTransaction, Leaf, LeafId, OutPoint, Transaction, Txid};
 
-use crate::LeafStorage;
+use crate::{Error, LeafStorage, Result};
 
 #[derive(Default)]
 pub struct TransactionChecker {
-    buffer_leaf_ids: BTreeSet<LeafId>,
-    used_buffer_leaf_ids: BTreeSet<LeafId>,
+    // outputs of the transactions filled so far
+    buffer_leaves: BTreeMap<LeafId, Leaf>,
+    // inputs of the transactions claimed so far
+    used_leaf_ids: BTreeSet<LeafId>,
     operators: BTreeMap<LeafId, Leaf>,
 }
 
 impl TransactionChecker {
+    /// Fill the inputs of `transaction` and claim them, see `fill_leaf_id` and `claim_inputs`.
     pub async fn check_leaf_id<S>(
         &mut self,
         leaf_storage: &S,
         transaction: Transaction,
     ) -> Result<FilledTransaction>
+    where
+        S: LeafStorage,
+    {
+        let filled_tx = self.fill_leaf_id(leaf_storage, transaction).await?;
+        self.claim_inputs(&filled_tx)?;
+
+        Ok(filled_tx)
+    }
+
+    /// Fill the inputs of `transaction` without claiming them.
+    ///
+    /// Its outputs are buffered, so later transactions can spend them. Inputs claimed by
+    /// earlier transactions are accepted, which one of the spenders wins is settled by
+    /// `claim_inputs`.
+    pub async fn fill_leaf_id<S>(
+        &mut self,
+        leaf_storage: &S,
+        transaction: Transaction,
+    ) -> Result<FilledTransaction>
     where
         S: LeafStorage,
     {
@@ -26,61 +47,288 @@ impl TransactionChecker {
         let txid = unsigned.hash()?;
 
         if unsigned.inputs.len() != transaction.unlockers.len() {
-            return Err(anyhow::anyhow!(
-                "Input length mismatch for txid: {:?}",
-                txid
-            ));
-        }
-
-        for (i, _leaf) in unsigned.outputs.iter().enumerate() {
-            self.buffer_leaf_ids.insert(LeafId {
-                txid: txid.clone(),
-                index: i as u32,
+            return Err(Error::InputLengthMismatch {
+                txid,
+                inputs: unsigned.inputs.len(),
+                unlockers: transaction.unlockers.len(),
             });
         }
 
+        // check every input before touching the checker state, so a rejected transaction
+        // leaves it as it was
+        let mut seen = BTreeSet::new();
         let mut filled_tx_inputs = Vec::new();
+        let mut operators = BTreeMap::new();
 
-        for leaf_id in &unsigned.inputs {
-            if self.used_buffer_leaf_ids.contains(leaf_id) {
-                return Err(anyhow::anyhow!("Input leaf id already used: {:?}", leaf_id));
+        for (input, leaf_id) in unsigned.inputs.iter().enumerate() {
+            if !seen.insert(leaf_id) {
+                return Err(Error::InputAlreadyUsed {
+                    txid,
+                    input,
+                    leaf_id: leaf_id.clone(),
+                });
             }
 
-            // if inputs in buffer_leaf_ids or in storage, pass validate
-            if self.buffer_leaf_ids.contains(&leaf_id) {
-                self.used_buffer_leaf_ids.insert(leaf_id.clone());
-                continue;
-            }
+            // inputs come from earlier outputs of the batch or from storage
+            let leaf = match self.buffer_leaves.get(leaf_id) {
+                Some(leaf) => leaf.clone(),
+                None => {
+                    let Some(leaf) = leaf_storage.get_leaf(leaf_id).await? else {
+                        return Err(Error::InputNotFound {
+                            txid,
+                            input,
+                            leaf_id: leaf_id.clone(),
+                        });
+                    };
 
-            let leaf = leaf_storage
-                .get_leaf(&leaf_id)
-                .await?
-                .ok_or(anyhow::anyhow!(
-                    "Input leaf not found in storage: {:?}",
-                    leaf_id
-                ))?;
+                    if leaf_storage.is_spent(leaf_id).await? {
+                        return Err(Error::InputAlreadySpent {
+                            txid,
+                            input,
+                            leaf_id: leaf_id.clone(),
+                        });
+                    }
 
-            self.used_buffer_leaf_ids.insert(leaf_id.clone());
+                    leaf
+                }
+            };
 
             if let Some(operator) = leaf.operator.clone() {
-                let operator_leaf = leaf_storage.get_leaf(&operator).await?;
-                if let Some(operator_leaf) = operator_leaf {
-                    self.operators.insert(operator, operator_leaf);
-                } else {
-                    return Err(anyhow::anyhow!(
-                        "Operator leaf not found in storage: {:?}",
-                        operator
-                    ));
-                }
+                let operator_leaf = self.live_operator(leaf_storage, &txid, &operator).await?;
+                operators.insert(operator, operator_leaf);
             }
 
             filled_tx_inputs.push(leaf);
         }
 
+        // outputs placed under an operator must be approved by it too
+        for leaf in &unsigned.outputs {
+            let Some(operator) = &leaf.operator else {
+                continue;
+            };
+
+            if operators.contains_key(operator) || self.operators.contains_key(operator) {
+                continue;
+            }
+
+            let operator_leaf = self.live_operator(leaf_storage, &txid, operator).await?;
+            operators.insert(operator.clone(), operator_leaf);
+        }
+
+        self.operators.extend(operators);
+
+        for (i, leaf) in unsigned.outputs.iter().enumerate() {
+            let leaf_id = OutPoint {
+                txid: txid.clone(),
+                index: i as u32,
+            }
+            .leaf_id();
+
+            self.buffer_leaves.insert(leaf_id, leaf.clone());
+        }
+
         Ok(FilledTransaction {
+            txid,
+            unsigned,
             inputs: filled_tx_inputs,
             unlockers: transaction.unlockers,
-            outputs: unsigned.outputs,
         })
     }
+
+    /// Claim the inputs of `filled_tx`, failing if an earlier transaction claimed one.
+    pub fn claim_inputs(&mut self, filled_tx: &FilledTransaction) -> Result<()> {
+        for (input, leaf_id) in filled_tx.unsigned.inputs.iter().enumerate() {
+            if self.used_leaf_ids.contains(leaf_id) {
+                return Err(Error::InputAlreadyUsed {
+                    txid: filled_tx.txid.clone(),
+                    input,
+                    leaf_id: leaf_id.clone(),
+                });
+            }
+        }
+
+        self.used_leaf_ids
+            .extend(filled_tx.unsigned.inputs.iter().cloned());
+
+        Ok(())
+    }
+
+    pub fn get_operator(&self, operator: &LeafId) -> Option<&Leaf> {
+        self.operators.get(operator)
+    }
+
+    /// Look a leaf up in the outputs checked so far, then in storage.
+    ///
+    /// Leaves created earlier in the batch, e.g. a freshly deployed operator or code leaf,
+    /// can be used before the batch is committed.
+    pub async fn get_leaf<S>(&self, leaf_storage: &S, leaf_id: &LeafId) -> Result<Option<Leaf>>
+    where
+        S: LeafStorage,
+    {
+        if let Some(leaf) = self.buffer_leaves.get(leaf_id) {
+            return Ok(Some(leaf.clone()));
+        }
+
+        leaf_storage.get_leaf(leaf_id).await
+    }
+
+    /// Look up the operator leaf `operator` of `txid`, which must exist and be unspent.
+    async fn live_operator<S>(
+        &self,
+        leaf_storage: &S,
+        txid: &Txid,
+        operator: &LeafId,
+    ) -> Result<Leaf>
+    where
+        S: LeafStorage,
+    {
+        let Some(operator_leaf) = self.get_leaf(leaf_storage, operator).await? else {
+            return Err(Error::OperatorNotFound {
+                txid: txid.clone(),
+                leaf_id: operator.clone(),
+            });
+        };
+
+        if self.is_spent(leaf_storage, operator).await? {
+            return Err(Error::OperatorSpent {
+                txid: txid.clone(),
+                leaf_id: operator.clone(),
+            });
+        }
+
+        Ok(operator_leaf)
+    }
+
+    /// Whether `leaf_id` was spent by an earlier commit.
+    ///
+    /// Leaves created earlier in the batch are never spent.
+    pub async fn is_spent<S>(&self, leaf_storage: &S, leaf_id: &LeafId) -> Result<bool>
+    where
+        S: LeafStorage,
+    {
+        if self.buffer_leaves.contains_key(leaf_id) {
+            return Ok(false);
+        }
+
+        leaf_storage.is_spent(leaf_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::FixedBytes;
+
+    use super::*;
+    use crate::{
+        CommittableStorage, Storage, StorageConfig, storages::MemoryStorage,
+        test_utils::transaction,
+    };
+
+    #[tokio::test]
+    async fn test_fill_buffered_inputs() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 1,
+        });
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+
+        let mut checker = TransactionChecker::default();
+
+        let parent = checker
+            .check_leaf_id(&leaf_storage, transaction(1, vec![]))
+            .await
+            .unwrap();
+        let output = OutPoint {
+            txid: parent.txid.clone(),
+            index: 0,
+        }
+        .leaf_id();
+
+        let child = checker
+            .check_leaf_id(&leaf_storage, transaction(2, vec![output.clone()]))
+            .await
+            .unwrap();
+        assert_eq!(child.inputs, parent.unsigned.outputs);
+
+        // already spent by the child
+        let err = checker
+            .check_leaf_id(&leaf_storage, transaction(3, vec![output.clone()]))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            Error::InputAlreadyUsed { input: 0, leaf_id, .. } if leaf_id == output
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spent_in_storage() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 1,
+        });
+        let stored = FixedBytes([9u8; 32]);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        let leaf = transaction(1, vec![]).unsigned.outputs.remove(0);
+        leaf_storage.store_leaf(&stored, leaf).await.unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&stored).await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        let err = TransactionChecker::default()
+            .check_leaf_id(&leaf_storage, transaction(2, vec![stored]))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InputAlreadySpent { input: 0, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_spent_operator() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 1,
+        });
+        let operator = FixedBytes([8u8; 32]);
+        let governed = FixedBytes([9u8; 32]);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        let leaf = transaction(1, vec![]).unsigned.outputs.remove(0);
+        leaf_storage
+            .store_leaf(&operator, leaf.clone())
+            .await
+            .unwrap();
+        let leaf = Leaf {
+            operator: Some(operator.clone()),
+            ..leaf
+        };
+        leaf_storage.store_leaf(&governed, leaf).await.unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&operator).await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+
+        // operator of an input
+        let err = TransactionChecker::default()
+            .check_leaf_id(&leaf_storage, transaction(2, vec![governed]))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::OperatorSpent { leaf_id, .. } if leaf_id == operator));
+
+        // operator of an output
+        let mut placed = transaction(3, vec![]);
+        placed.unsigned.outputs[0].operator = Some(operator.clone());
+        let err = TransactionChecker::default()
+            .check_leaf_id(&leaf_storage, placed)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::OperatorSpent { leaf_id, .. } if leaf_id == operator));
+    }
 }
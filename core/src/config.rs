@@ -8,4 +8,30 @@ pub struct Config {}
 pub struct WasmExecutorConfig {
     pub config_path: PathBuf,
     pub cache_path: Option<PathBuf>,
+    /// Number of compiled modules kept in memory, keyed by code leaf.
+    pub module_cache_size: usize,
+    /// Fuel available to a single unlocker or operator run.
+    pub script_fuel_limit: u64,
+    /// Fuel shared by all unlockers and operators of one transaction.
+    pub transaction_fuel_limit: u64,
+    /// Linear memory a script may use, in 64 KiB pages.
+    pub max_memory_pages: u32,
+    pub max_table_elements: u32,
+    /// Instances a script store may create, each run needs one.
+    pub max_instances: usize,
+    /// Nested calls a script may make before it is stopped with a stack limit.
+    pub max_call_depth: u32,
+    /// Native stack available to wasm code, in bytes.
+    ///
+    /// Only a host safety net, the native frame size depends on the compiler, so it must be
+    /// large enough for `max_call_depth` frames.
+    pub max_wasm_stack: usize,
+    /// Threads validating the scripts of a batch, 0 uses every available core.
+    pub script_workers: usize,
+}
+
+pub struct StorageConfig {
+    /// Number of past versions `revert_to_version` can go back to. Undo journals of older
+    /// commits are dropped.
+    pub retained_versions: usize,
 }
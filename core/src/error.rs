@@ -0,0 +1,208 @@
+use bbm_primitives::{Address, LeafId, Txid};
+
+use crate::executors::LimitError;
+
+/// Errors of the core crate.
+///
+/// `code` maps each variant to a number that stays the same across releases, so clients
+/// can match on it instead of the message.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("duplicate transaction in batch: {0:?}")]
+    DuplicateTransaction(Txid),
+
+    #[error("leaf {leaf_id:?} spent by both {first:?} and {second:?}")]
+    ConflictingSpend {
+        leaf_id: LeafId,
+        first: Txid,
+        second: Txid,
+    },
+
+    #[error("transaction batch contains a dependency cycle")]
+    DependencyCycle,
+
+    #[error("{inputs} inputs but {unlockers} unlockers in {txid:?}")]
+    InputLengthMismatch {
+        txid: Txid,
+        inputs: usize,
+        unlockers: usize,
+    },
+
+    #[error("input {input} of {txid:?}: leaf {leaf_id:?} already used")]
+    InputAlreadyUsed {
+        txid: Txid,
+        input: usize,
+        leaf_id: LeafId,
+    },
+
+    #[error("input {input} of {txid:?}: leaf {leaf_id:?} not found in storage")]
+    InputNotFound {
+        txid: Txid,
+        input: usize,
+        leaf_id: LeafId,
+    },
+
+    #[error("input {input} of {txid:?}: leaf {leaf_id:?} already spent")]
+    InputAlreadySpent {
+        txid: Txid,
+        input: usize,
+        leaf_id: LeafId,
+    },
+
+    #[error("operator leaf {leaf_id:?} of {txid:?} not found in storage")]
+    OperatorNotFound { txid: Txid, leaf_id: LeafId },
+
+    #[error("operator leaf {leaf_id:?} of {txid:?} already spent")]
+    OperatorSpent { txid: Txid, leaf_id: LeafId },
+
+    #[error("script of input {input} of {txid:?}: {source}")]
+    Script {
+        txid: Txid,
+        input: usize,
+        source: ScriptError,
+    },
+
+    #[error("operator {leaf_id:?} of {txid:?}: {source}")]
+    Operator {
+        txid: Txid,
+        leaf_id: LeafId,
+        source: ScriptError,
+    },
+
+    #[error("commit version {version} must be greater than current version {current}")]
+    VersionNotIncreasing { version: u64, current: u64 },
+
+    #[error("version {version} is no longer retained, cannot revert to {target}")]
+    VersionNotRetained { version: u64, target: u64 },
+
+    #[error("version not found in storage: {0}")]
+    VersionNotFound(u64),
+
+    #[error("spent leaf not found in storage: {0:?}")]
+    SpentLeafNotFound(LeafId),
+
+    #[error("undo log truncated")]
+    UndoLogTruncated,
+
+    #[error("trailing bytes after undo log: {0}")]
+    UndoLogTrailingBytes(usize),
+
+    #[error(transparent)]
+    Database(#[from] redb::Error),
+
+    #[error(transparent)]
+    Primitives(#[from] bbm_primitives::Error),
+
+    #[error(transparent)]
+    Wasm(#[from] wasmtime::Error),
+}
+
+impl Error {
+    /// Stable code of the error.
+    ///
+    /// 1xx batch, 2xx inputs, 3xx unlockers, 4xx operators, 5xx storage, 9xx others. Script
+    /// and operator failures add the code of their `ScriptError`.
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::DuplicateTransaction(_) => 101,
+            Self::ConflictingSpend { .. } => 102,
+            Self::DependencyCycle => 103,
+            Self::InputLengthMismatch { .. } => 201,
+            Self::InputAlreadyUsed { .. } => 202,
+            Self::InputNotFound { .. } => 203,
+            Self::InputAlreadySpent { .. } => 204,
+            Self::OperatorNotFound { .. } => 205,
+            Self::OperatorSpent { .. } => 206,
+            Self::Script { source, .. } => 300 + source.code(),
+            Self::Operator { source, .. } => 400 + source.code(),
+            Self::VersionNotIncreasing { .. } => 501,
+            Self::VersionNotRetained { .. } => 502,
+            Self::VersionNotFound(_) => 503,
+            Self::SpentLeafNotFound(_) => 504,
+            Self::UndoLogTruncated => 505,
+            Self::UndoLogTrailingBytes(_) => 506,
+            Self::Database(_) => 507,
+            Self::Primitives(_) => 901,
+            Self::Wasm(_) => 902,
+        }
+    }
+}
+
+// redb reports each kind of operation with its own error type
+macro_rules! impl_from_redb {
+    ($($ty:ident),*) => {
+        $(
+            impl From<redb::$ty> for Error {
+                fn from(e: redb::$ty) -> Self {
+                    Self::Database(e.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_redb!(
+    DatabaseError,
+    TransactionError,
+    TableError,
+    StorageError,
+    CommitError
+);
+
+/// Why an unlocker or an operator rejected a transaction.
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptError {
+    #[error("malformed unlock script: {0}")]
+    Malformed(#[from] bbm_primitives::Error),
+
+    #[error("unlock script address {actual:?} does not match leaf owner {expected:?}")]
+    AddressMismatch { expected: Address, actual: Address },
+
+    #[error("unsupported unlock script type: {0}")]
+    UnsupportedType(u8),
+
+    #[error("code leaf not found: {0:?}")]
+    CodeNotFound(LeafId),
+
+    #[error("invalid module: {0}")]
+    InvalidModule(wasmtime::Error),
+
+    #[error("rejected with exit code {0}")]
+    Rejected(u32),
+
+    #[error("trapped: {0}")]
+    Trapped(String),
+
+    #[error("ran out of fuel")]
+    OutOfFuel,
+
+    #[error("does not export `_entry`")]
+    MissingEntry,
+
+    #[error(transparent)]
+    LimitExceeded(LimitError),
+
+    #[error("code leaf already spent: {0:?}")]
+    CodeSpent(LeafId),
+}
+
+impl ScriptError {
+    /// Stable code of the error, between 1 and 99.
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Malformed(_) => 1,
+            Self::AddressMismatch { .. } => 2,
+            Self::UnsupportedType(_) => 3,
+            Self::CodeNotFound(_) => 4,
+            Self::InvalidModule(_) => 5,
+            Self::Rejected(_) => 6,
+            Self::Trapped(_) => 7,
+            Self::OutOfFuel => 8,
+            Self::MissingEntry => 9,
+            Self::LimitExceeded(_) => 10,
+            Self::CodeSpent(_) => 11,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
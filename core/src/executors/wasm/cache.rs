@@ -0,0 +1,136 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use bbm_primitives::LeafId;
+use lru::LruCache;
+use wasmtime::{Engine, Module};
+
+use crate::executors::limit_call_depth;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+}
+
+/// In-process LRU of compiled modules, keyed by the leaf holding the code.
+pub struct ModuleCache {
+    modules: Mutex<LruCache<LeafId, Module>>,
+    max_call_depth: u32,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ModuleCache {
+    pub fn new(capacity: usize, max_call_depth: u32) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            modules: Mutex::new(LruCache::new(capacity)),
+            max_call_depth,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, leaf_id: &LeafId) -> Option<Module> {
+        let module = self.lock().get(leaf_id).cloned();
+
+        if module.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        module
+    }
+
+    /// Compile `code` limited to `max_call_depth` nested calls, see `limit_call_depth`.
+    pub fn compile(&self, engine: &Engine, leaf_id: &LeafId, code: &[u8]) -> Result<Module> {
+        // Compile outside the lock so a slow module does not block other lookups.
+        let code = limit_call_depth(code, self.max_call_depth)?;
+        let module = Module::from_binary(engine, &code)?;
+        self.lock().put(leaf_id.clone(), module.clone());
+
+        Ok(module)
+    }
+
+    pub fn get_or_compile(&self, engine: &Engine, leaf_id: &LeafId, code: &[u8]) -> Result<Module> {
+        if let Some(module) = self.get(leaf_id) {
+            return Ok(module);
+        }
+
+        self.compile(engine, leaf_id, code)
+    }
+
+    /// Drop the module compiled from `leaf_id`, called once the code leaf is spent.
+    pub fn evict(&self, leaf_id: &LeafId) {
+        self.lock().pop(leaf_id);
+    }
+
+    pub fn stats(&self) -> ModuleCacheStats {
+        ModuleCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: self.lock().len(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<LeafId, Module>> {
+        self.modules.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::FixedBytes;
+    use wasmtime::Config;
+
+    use super::*;
+    use crate::executors::wasm::consensus_profile;
+
+    const CODE: &str = r#"(module (func (export "_entry") (result i32) (i32.const 0)))"#;
+
+    #[test]
+    fn test_module_cache() {
+        let mut config = Config::new();
+        consensus_profile(&mut config);
+        let engine = Engine::new(&config).unwrap();
+        let code = wat::parse_str(CODE).unwrap();
+        let cache = ModuleCache::new(2, 64);
+
+        let leaf_a = FixedBytes([1u8; 32]);
+        let leaf_b = FixedBytes([2u8; 32]);
+        let leaf_c = FixedBytes([3u8; 32]);
+
+        cache.get_or_compile(&engine, &leaf_a, &code).unwrap();
+        cache.get_or_compile(&engine, &leaf_a, &code).unwrap();
+        assert_eq!(
+            cache.stats(),
+            ModuleCacheStats {
+                hits: 1,
+                misses: 1,
+                len: 1
+            }
+        );
+
+        // leaf_a is the least recently used entry once leaf_b is touched
+        cache.get_or_compile(&engine, &leaf_b, &code).unwrap();
+        cache.get_or_compile(&engine, &leaf_c, &code).unwrap();
+        cache.get_or_compile(&engine, &leaf_a, &code).unwrap();
+        assert_eq!(cache.stats().misses, 4);
+        assert_eq!(cache.stats().len, 2);
+
+        cache.evict(&leaf_a);
+        assert_eq!(cache.stats().len, 1);
+        cache.get_or_compile(&engine, &leaf_a, &code).unwrap();
+        assert_eq!(cache.stats().misses, 5);
+    }
+}
@@ -0,0 +1,172 @@
+use std::convert::Infallible;
+
+use anyhow::Result;
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, ExportKind, ExportSection, Function, GlobalSection,
+    GlobalType, Module, SectionId, ValType,
+    reencode::{Error, Reencode, utils},
+};
+use wasmparser::{
+    ExportSectionReader, FunctionBody, GlobalSectionReader, Operator, Parser, Payload, TypeRef,
+};
+
+/// Export of the call depth global added by `limit_call_depth`.
+pub(crate) const CALL_DEPTH_EXPORT: &str = "__bbm_call_depth";
+
+/// Rewrite `code` so it traps once its calls nest deeper than `max_call_depth`.
+///
+/// Every `call` and `call_indirect` bumps a global counter, which the caller reads back
+/// through `CALL_DEPTH_EXPORT` to tell the limit apart from other traps. Unlike the native
+/// stack, the count does not depend on the host or the compiler.
+pub(crate) fn limit_call_depth(code: &[u8], max_call_depth: u32) -> Result<Vec<u8>> {
+    // the counter is appended after the imported and defined globals
+    let mut global = 0;
+    for payload in Parser::new(0).parse_all(code) {
+        match payload? {
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    if matches!(import?.ty, TypeRef::Global(_)) {
+                        global += 1;
+                    }
+                }
+            }
+            Payload::GlobalSection(globals) => global += globals.count(),
+            _ => {}
+        }
+    }
+
+    let mut depth = CallDepth {
+        global,
+        max_call_depth,
+        global_added: false,
+        export_added: false,
+    };
+    let mut module = Module::new();
+    depth.parse_core_module(&mut module, Parser::new(0), code)?;
+
+    Ok(module.finish())
+}
+
+struct CallDepth {
+    global: u32,
+    max_call_depth: u32,
+    global_added: bool,
+    export_added: bool,
+}
+
+impl CallDepth {
+    fn add_global(&mut self, globals: &mut GlobalSection) {
+        let ty = GlobalType {
+            val_type: ValType::I32,
+            mutable: true,
+            shared: false,
+        };
+        globals.global(ty, &ConstExpr::i32_const(0));
+        self.global_added = true;
+    }
+
+    fn add_export(&mut self, exports: &mut ExportSection) {
+        exports.export(CALL_DEPTH_EXPORT, ExportKind::Global, self.global);
+        self.export_added = true;
+    }
+
+    fn enter(&self, f: &mut Function) {
+        f.instructions()
+            .global_get(self.global)
+            .i32_const(1)
+            .i32_add()
+            .global_set(self.global)
+            .global_get(self.global)
+            .i32_const(self.max_call_depth as i32)
+            .i32_gt_u()
+            .if_(BlockType::Empty)
+            .unreachable()
+            .end();
+    }
+
+    fn leave(&self, f: &mut Function) {
+        f.instructions()
+            .global_get(self.global)
+            .i32_const(1)
+            .i32_sub()
+            .global_set(self.global);
+    }
+}
+
+impl Reencode for CallDepth {
+    type Error = Infallible;
+
+    fn parse_global_section(
+        &mut self,
+        globals: &mut GlobalSection,
+        section: GlobalSectionReader<'_>,
+    ) -> Result<(), Error> {
+        utils::parse_global_section(self, globals, section)?;
+        self.add_global(globals);
+        Ok(())
+    }
+
+    fn parse_export_section(
+        &mut self,
+        exports: &mut ExportSection,
+        section: ExportSectionReader<'_>,
+    ) -> Result<(), Error> {
+        utils::parse_export_section(self, exports, section)?;
+        self.add_export(exports);
+        Ok(())
+    }
+
+    // add the global and export sections when the module has none
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut Module,
+        _after: Option<SectionId>,
+        before: Option<SectionId>,
+    ) -> Result<(), Error> {
+        use SectionId::*;
+
+        let past_exports = matches!(
+            before,
+            None | Some(Start | Element | DataCount | Code | Data)
+        );
+
+        if !self.global_added && (past_exports || before == Some(Export)) {
+            let mut globals = GlobalSection::new();
+            self.add_global(&mut globals);
+            module.section(&globals);
+        }
+
+        if !self.export_added && past_exports {
+            let mut exports = ExportSection::new();
+            self.add_export(&mut exports);
+            module.section(&exports);
+        }
+
+        Ok(())
+    }
+
+    fn parse_function_body(
+        &mut self,
+        code: &mut CodeSection,
+        func: FunctionBody<'_>,
+    ) -> Result<(), Error> {
+        let mut f = self.new_function_with_parsed_locals(&func)?;
+        let mut reader = func.get_operators_reader()?;
+
+        while !reader.eof() {
+            let op = reader.read()?;
+            let call = matches!(op, Operator::Call { .. } | Operator::CallIndirect { .. });
+
+            if call {
+                self.enter(&mut f);
+            }
+            f.instruction(&self.instruction(op)?);
+            if call {
+                self.leave(&mut f);
+            }
+        }
+
+        code.function(&f);
+        Ok(())
+    }
+}
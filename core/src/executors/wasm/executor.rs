@@ -1,17 +1,57 @@
-use std::path::Path;
+use std::{
+    panic,
+    path::Path,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+};
 
-use anyhow::Result;
-use bbm_primitives::{LeafId, Script, UnsignedTransaction};
-use wasmtime::{Cache, CacheConfig, Config, Engine};
+use bbm_primitives::{FilledTransaction, LeafId, UnlockScript, UnlockScriptType};
+use wasmtime::{Cache, CacheConfig, Config, Engine, Linker};
 
-use crate::{WasmExecutorConfig, executors::WasmInstance};
+use super::ffi;
+use crate::{
+    Error, GovernedLeaves, LeafStorage, Result, ScriptError, TransactionChecker,
+    WasmExecutorConfig,
+    executors::{
+        ExecutorStore, FuelUsage, LimitError, ModuleCache, ModuleCacheStats, PreparedScript,
+        PreparedTransaction, ScriptOutcome, ScriptReport, WasmInstance, WasmLimits,
+    },
+};
 
+/// Clones share the engine and the module cache.
+#[derive(Clone)]
 pub struct WasmExecutor {
     engine: Engine,
+    linker: Linker<ExecutorStore>,
+    modules: Arc<ModuleCache>,
+    limits: WasmLimits,
+    script_fuel_limit: u64,
+    transaction_fuel_limit: u64,
+    script_workers: usize,
 }
 
 impl WasmExecutor {
     pub fn new(config: &WasmExecutorConfig, home_path: &Path) -> Result<Self> {
+        let modules = Arc::new(ModuleCache::new(
+            config.module_cache_size,
+            config.max_call_depth,
+        ));
+        let limits = WasmLimits {
+            max_memory_pages: config.max_memory_pages,
+            max_table_elements: config.max_table_elements,
+            max_instances: config.max_instances,
+            max_call_depth: config.max_call_depth,
+        };
+        let script_fuel_limit = config.script_fuel_limit;
+        let transaction_fuel_limit = config.transaction_fuel_limit;
+        let script_workers = match config.script_workers {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+
         let mut cache_config = CacheConfig::from_file(Some(&config.config_path))?;
         if let Some(cache_path) = config.cache_path.clone() {
             cache_config.with_directory(cache_path);
@@ -21,44 +61,734 @@ impl WasmExecutor {
 
         let cache = Cache::new(cache_config)?;
 
+        let max_wasm_stack = config.max_wasm_stack;
+
         let mut config = Config::new();
         config.cache(Some(cache));
+        consensus_profile(&mut config);
+        config.consume_fuel(true);
+        config.max_wasm_stack(max_wasm_stack);
 
         let engine = Engine::new(&config)?;
 
-        Ok(Self { engine })
+        let mut linker = Linker::new(&engine);
+        ffi::add_to_linker(&mut linker)?;
+
+        Ok(Self {
+            engine,
+            linker,
+            modules,
+            limits,
+            script_fuel_limit,
+            transaction_fuel_limit,
+            script_workers,
+        })
+    }
+
+    /// Resolve the unlocker of input `input` of `transaction` without running it.
+    ///
+    /// The unlocker is an encoded `UnlockScript` followed by witness data. The script must
+    /// derive the owner address of the input, its code is loaded from the referenced code
+    /// leaf, which may be an output checked earlier in the batch.
+    pub async fn prepare_script<S>(
+        &self,
+        leaf_storage: &S,
+        checker: &TransactionChecker,
+        transaction: &FilledTransaction,
+        input: usize,
+    ) -> Result<PreparedScript>
+    where
+        S: LeafStorage,
+    {
+        let script_error = |source| Error::Script {
+            txid: transaction.txid.clone(),
+            input,
+            source,
+        };
+
+        let leaf = &transaction.inputs[input];
+        let unlocker = &transaction.unlockers[input].0;
+
+        let script = UnlockScript::from_slice(unlocker)
+            .map_err(|e| script_error(ScriptError::Malformed(e)))?;
+
+        let address = script
+            .address()
+            .map_err(|e| script_error(ScriptError::Malformed(e)))?;
+        if address != leaf.owner {
+            return Err(script_error(ScriptError::AddressMismatch {
+                expected: leaf.owner.clone(),
+                actual: address,
+            }));
+        }
+
+        if !matches!(script.ty, UnlockScriptType::Wasm) {
+            return Err(script_error(ScriptError::UnsupportedType(
+                script.ty.to_u8(),
+            )));
+        }
+
+        let code_leaf_id = LeafId::from_slice(&script.code_leaf)
+            .map_err(|e| script_error(ScriptError::Malformed(e)))?;
+        // a cached module may come from a code leaf that was never applied or since spent,
+        // check it is still live
+        let Some(code_leaf) = checker.get_leaf(leaf_storage, &code_leaf_id).await? else {
+            return Err(script_error(ScriptError::CodeNotFound(code_leaf_id)));
+        };
+        if checker.is_spent(leaf_storage, &code_leaf_id).await? {
+            return Err(script_error(ScriptError::CodeSpent(code_leaf_id)));
+        }
+        let module = self
+            .modules
+            .get_or_compile(&self.engine, &code_leaf_id, &code_leaf.data.0)
+            .map_err(|e| script_error(ScriptError::InvalidModule(e)))?;
+
+        let witness = unlocker[script.encoded_len()..].to_vec();
+
+        Ok(PreparedScript {
+            module,
+            witness,
+            args: script.args,
+        })
+    }
+
+    /// Run the prepared scripts of every transaction, returning the fuel used by each.
+    ///
+    /// Transactions are spread over the worker threads, the scripts of one transaction run in
+    /// order on a single worker as they share its fuel. If several transactions fail, the
+    /// error of the first one in `transactions` is returned.
+    pub async fn validate_scripts(
+        &self,
+        transactions: Vec<PreparedTransaction>,
+    ) -> Result<Vec<FuelUsage>> {
+        let mut fuel_usages = Vec::with_capacity(transactions.len());
+
+        for report in self.spawn_scripts(transactions, true).await? {
+            // reports are only left out after a failure
+            let Some(report) = report else {
+                break;
+            };
+
+            match report.failure {
+                Some((_, e)) => return Err(e),
+                None => fuel_usages.push(report.fuel),
+            }
+        }
+
+        Ok(fuel_usages)
+    }
+
+    /// Like `validate_scripts`, but runs every transaction and reports each one separately.
+    pub async fn run_scripts(
+        &self,
+        transactions: Vec<PreparedTransaction>,
+    ) -> Result<Vec<ScriptReport>> {
+        let reports = self.spawn_scripts(transactions, false).await?;
+
+        Ok(reports.into_iter().map(Option::unwrap_or_default).collect())
+    }
+
+    /// Run `execute_scripts` on the blocking pool, so the worker threads it waits for do not
+    /// stall the async runtime.
+    async fn spawn_scripts(
+        &self,
+        transactions: Vec<PreparedTransaction>,
+        fail_fast: bool,
+    ) -> Result<Vec<Option<ScriptReport>>> {
+        let executor = self.clone();
+        let task =
+            tokio::task::spawn_blocking(move || executor.execute_scripts(&transactions, fail_fast));
+
+        match task.await {
+            Ok(reports) => Ok(reports),
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(Error::Wasm(e.into())),
+        }
+    }
+
+    /// With `fail_fast`, transactions not started yet are left out after the first failure.
+    fn execute_scripts(
+        &self,
+        transactions: &[PreparedTransaction],
+        fail_fast: bool,
+    ) -> Vec<Option<ScriptReport>> {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let reports = Mutex::new((0..transactions.len()).map(|_| None).collect::<Vec<_>>());
+
+        let workers = self.script_workers.min(transactions.len());
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    // every transaction before a taken one has been taken too, so the first
+                    // failure in batch order always has a report
+                    while !(fail_fast && failed.load(Ordering::Relaxed)) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(transaction) = transactions.get(i) else {
+                            break;
+                        };
+
+                        let mut report = ScriptReport::default();
+                        for (input, script) in transaction.scripts.iter().enumerate() {
+                            let result =
+                                self.run_script(&mut report.fuel, script, transaction, input);
+
+                            if let Err(e) = result {
+                                report.failure = Some((input, e));
+                                failed.store(true, Ordering::Relaxed);
+                                break;
+                            }
+                        }
+
+                        reports.lock().unwrap_or_else(PoisonError::into_inner)[i] = Some(report);
+                    }
+                });
+            }
+        });
+
+        reports.into_inner().unwrap_or_else(PoisonError::into_inner)
     }
 
-    pub fn validate_script(
+    fn run_script(
         &self,
-        script: &Script<'_>,
-        transaction: &UnsignedTransaction,
-        unlocker: Vec<u8>,
+        fuel: &mut FuelUsage,
+        script: &PreparedScript,
+        transaction: &PreparedTransaction,
+        input: usize,
     ) -> Result<()> {
+        let script_error = |source| Error::Script {
+            txid: transaction.txid.clone(),
+            input,
+            source,
+        };
+
+        let store = ExecutorStore::with_encoded(
+            transaction.unsigned.clone(),
+            Some(script.witness.clone()),
+            Some(script.args.clone()),
+            self.limits,
+        );
+
         let mut instance = WasmInstance::new(
             &self.engine,
-            script.code().to_vec(),
-            Some(script.args().to_vec()),
-            &transaction,
-            Some(unlocker),
-        )?;
+            &self.linker,
+            &script.module,
+            store,
+            self.fuel_limit(fuel),
+        )
+        .map_err(|e| script_error(instance_error(e)))?;
 
-        instance.run()?;
+        let outcome = instance
+            .run()
+            .map_err(|e| script_error(instance_error(e)))?;
+        fuel.unlockers.push(instance.fuel_consumed());
 
-        Ok(())
+        check_outcome(outcome).map_err(script_error)
     }
 
+    /// Run an operator against `transaction`, fuel used is appended to `fuel.operators`.
+    ///
+    /// The operator can read the indices of the inputs and outputs it governs.
     pub fn validate_operator(
         &self,
-        operator: Vec<u8>,
-        // TODO: use leaf id to cache
-        _operator_leaf_id: LeafId,
-        transaction: &UnsignedTransaction,
+        fuel: &mut FuelUsage,
+        operator: &[u8],
+        operator_leaf_id: &LeafId,
+        governed: &GovernedLeaves,
+        transaction: &FilledTransaction,
     ) -> Result<()> {
-        let mut instance = WasmInstance::new(&self.engine, operator, None, &transaction, None)?;
+        let operator_error = |source| Error::Operator {
+            txid: transaction.txid.clone(),
+            leaf_id: operator_leaf_id.clone(),
+            source,
+        };
+
+        let module = self
+            .modules
+            .get_or_compile(&self.engine, operator_leaf_id, operator)
+            .map_err(|e| operator_error(ScriptError::InvalidModule(e)))?;
+
+        let store = ExecutorStore::new(&transaction.unsigned, None, None, self.limits)?
+            .with_governed(governed);
+
+        let mut instance = WasmInstance::new(
+            &self.engine,
+            &self.linker,
+            &module,
+            store,
+            self.fuel_limit(fuel),
+        )
+        .map_err(|e| operator_error(instance_error(e)))?;
+
+        let outcome = instance
+            .run()
+            .map_err(|e| operator_error(instance_error(e)))?;
+        fuel.operators.push(instance.fuel_consumed());
+
+        check_outcome(outcome).map_err(operator_error)
+    }
+
+    /// Fuel for the next run: the per-script limit, capped by what the transaction has left.
+    fn fuel_limit(&self, fuel: &FuelUsage) -> u64 {
+        let remaining = self.transaction_fuel_limit.saturating_sub(fuel.total());
+
+        self.script_fuel_limit.min(remaining)
+    }
+
+    /// Forget the compiled module of a code leaf that has been spent.
+    pub fn evict_module(&self, leaf_id: &LeafId) {
+        self.modules.evict(leaf_id);
+    }
+
+    pub fn module_cache_stats(&self) -> ModuleCacheStats {
+        self.modules.stats()
+    }
+}
+
+/// Pin the engine to a deterministic feature set so every node reaches the same verdict.
+///
+/// Proposals not listed here are rejected when a module is compiled. Threads and the
+/// component model are also unavailable as their crate features are not enabled, the `gc`
+/// feature is only enabled so reference types and exceptions can be turned off.
+pub(crate) fn consensus_profile(config: &mut Config) {
+    config.cranelift_nan_canonicalization(true);
+
+    // allowed proposals
+    config.wasm_bulk_memory(true);
+    config.wasm_multi_value(true);
+    config.wasm_simd(true);
+
+    // disallowed proposals
+    config.wasm_relaxed_simd(false);
+    config.wasm_shared_everything_threads(false);
+    config.wasm_multi_memory(false);
+    config.wasm_memory64(false);
+    config.wasm_tail_call(false);
+    config.wasm_extended_const(false);
+    config.wasm_custom_page_sizes(false);
+    config.wasm_wide_arithmetic(false);
+    config.wasm_stack_switching(false);
+    config.wasm_reference_types(false);
+    config.wasm_function_references(false);
+    config.wasm_exceptions(false);
+    config.wasm_gc(false);
+    config.gc_support(false);
+}
+
+// instantiation fails on a limit when the module declares more than it allows
+fn instance_error(e: wasmtime::Error) -> ScriptError {
+    match LimitError::find(&e) {
+        Some(limit) => ScriptError::LimitExceeded(limit),
+        None => ScriptError::InvalidModule(e),
+    }
+}
+
+fn check_outcome(outcome: ScriptOutcome) -> core::result::Result<(), ScriptError> {
+    match outcome {
+        ScriptOutcome::Success => Ok(()),
+        ScriptOutcome::Failed(code) => Err(ScriptError::Rejected(code)),
+        ScriptOutcome::Trapped(reason) => Err(ScriptError::Trapped(reason)),
+        ScriptOutcome::OutOfFuel => Err(ScriptError::OutOfFuel),
+        ScriptOutcome::MissingEntry => Err(ScriptError::MissingEntry),
+        ScriptOutcome::LimitExceeded(limit) => Err(ScriptError::LimitExceeded(limit)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::UnsignedTransaction;
+    use wasmtime::Module;
+
+    use super::*;
+    use crate::test_utils::{TempDir, executor};
+
+    fn transaction(nonce: u64) -> FilledTransaction {
+        let unsigned = UnsignedTransaction {
+            version: 1,
+            nonce,
+            inputs: vec![],
+            outputs: vec![],
+        };
+
+        FilledTransaction {
+            txid: unsigned.hash().unwrap(),
+            unsigned,
+            inputs: vec![],
+            unlockers: vec![],
+        }
+    }
+
+    fn run(
+        executor: &WasmExecutor,
+        wat: &str,
+        unlocker: Option<Vec<u8>>,
+    ) -> anyhow::Result<ScriptOutcome> {
+        let transaction = transaction(0).unsigned;
+
+        let code = wat::parse_str(wat).unwrap();
+        let module = executor
+            .modules
+            .compile(&executor.engine, &LeafId::default(), &code)
+            .unwrap();
+
+        let mut instance = WasmInstance::new(
+            &executor.engine,
+            &executor.linker,
+            &module,
+            ExecutorStore::new(&transaction, unlocker, None, executor.limits)?,
+            executor.script_fuel_limit,
+        )?;
+
+        instance.run()
+    }
+
+    #[test]
+    fn test_script_outcome() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        let outcome = run(
+            &executor,
+            r#"(module (func (export "_entry") (result i32) (i32.const 0)))"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(outcome, ScriptOutcome::Success);
+
+        let outcome = run(
+            &executor,
+            r#"(module (func (export "_entry") (result i32) (i32.const 7)))"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(outcome, ScriptOutcome::Failed(7));
+
+        let outcome = run(
+            &executor,
+            r#"(module (func (export "_entry") (result i32) unreachable))"#,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(outcome, ScriptOutcome::Trapped(_)));
+
+        let outcome = run(&executor, r#"(module (func (export "main")))"#, None).unwrap();
+        assert_eq!(outcome, ScriptOutcome::MissingEntry);
+
+        let outcome = run(
+            &executor,
+            r#"(module (func (export "_entry") (result i32) (loop (br 0)) (i32.const 0)))"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(outcome, ScriptOutcome::OutOfFuel);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        let wat = r#"(module
+            (memory 1)
+            (func (export "_entry") (result i32)
+                (drop (memory.grow (i32.const 1)))
+                (drop (memory.grow (i32.const 1)))
+                (i32.const 0)))"#;
+
+        let outcome = run(&executor, wat, None).unwrap();
+        assert_eq!(
+            outcome,
+            ScriptOutcome::LimitExceeded(LimitError::Memory {
+                requested: 3,
+                limit: 2
+            })
+        );
+
+        let wat = r#"(module (memory 3) (func (export "_entry") (result i32) (i32.const 0)))"#;
+        let e = run(&executor, wat, None).unwrap_err();
+        let e = instance_error(e);
+        assert!(matches!(
+            e,
+            ScriptError::LimitExceeded(LimitError::Memory { .. })
+        ));
+        assert_eq!(e.code(), 10);
+    }
+
+    #[test]
+    fn test_table_limit() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        // tables can only be grown with reference types, which are disabled
+        let wat =
+            r#"(module (table 17 funcref) (func (export "_entry") (result i32) (i32.const 0)))"#;
+        let e = instance_error(run(&executor, wat, None).unwrap_err());
+        assert!(matches!(
+            e,
+            ScriptError::LimitExceeded(LimitError::Table {
+                requested: 17,
+                limit: 16
+            })
+        ));
+
+        let wat =
+            r#"(module (table 16 funcref) (func (export "_entry") (result i32) (i32.const 0)))"#;
+        assert_eq!(run(&executor, wat, None).unwrap(), ScriptOutcome::Success);
+    }
+
+    #[test]
+    fn test_instance_limit() {
+        let home = TempDir::new();
+        let mut executor = executor(&home);
+        let wat = r#"(module (func (export "_entry") (result i32) (i32.const 0)))"#;
+
+        assert_eq!(run(&executor, wat, None).unwrap(), ScriptOutcome::Success);
+
+        executor.limits.max_instances = 0;
+        let e = instance_error(run(&executor, wat, None).unwrap_err());
+        assert!(matches!(
+            e,
+            ScriptError::LimitExceeded(LimitError::Instances {
+                requested: 1,
+                limit: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stack_limit() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        // `_entry` calls $f, which recurses `depth` more times
+        let recurse = |depth: u32| {
+            format!(
+                r#"(module
+                    (func $f (param i32) (result i32)
+                        (if (result i32) (local.get 0)
+                            (then (call $f (i32.sub (local.get 0) (i32.const 1))))
+                            (else (i32.const 0))))
+                    (func (export "_entry") (result i32) (call $f (i32.const {depth}))))"#
+            )
+        };
+
+        let limit = executor.limits.max_call_depth;
+        let outcome = run(&executor, &recurse(limit - 1), None).unwrap();
+        assert_eq!(outcome, ScriptOutcome::Success);
+
+        let outcome = run(&executor, &recurse(limit), None).unwrap();
+        assert_eq!(outcome, ScriptOutcome::LimitExceeded(LimitError::Stack));
+        assert_eq!(check_outcome(outcome).unwrap_err().code(), 10);
+
+        // stopped by the call depth long before the native stack or the fuel run out
+        let wat = r#"(module (func $f (export "_entry") (result i32) (call $f)))"#;
+        let outcome = run(&executor, wat, None).unwrap();
+        assert_eq!(outcome, ScriptOutcome::LimitExceeded(LimitError::Stack));
+    }
+
+    #[test]
+    fn test_consensus_profile() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+        let leaf_id = LeafId::default();
+
+        let compile = |wat: &str| {
+            let code = wat::parse_str(wat).unwrap();
+            executor.modules.compile(&executor.engine, &leaf_id, &code)
+        };
+
+        // bulk memory
+        let wat = r#"(module
+            (memory 1)
+            (func (memory.fill (i32.const 0) (i32.const 0) (i32.const 1))))"#;
+        assert!(compile(wat).is_ok());
+
+        // memory64
+        assert!(compile(r#"(module (memory i64 1))"#).is_err());
+        // threads
+        assert!(compile(r#"(module (memory 1 1 shared))"#).is_err());
+        // tail calls
+        assert!(compile(r#"(module (func $f (return_call $f)))"#).is_err());
+        // relaxed simd
+        let wat = r#"(module
+            (func (param v128) (result v128)
+                (i8x16.relaxed_swizzle (local.get 0) (local.get 0))))"#;
+        assert!(compile(wat).is_err());
+        // reference types
+        assert!(compile(r#"(module (func (param externref)))"#).is_err());
+        let wat = r#"(module
+            (func $f)
+            (elem declare func $f)
+            (func (result funcref) (ref.func $f)))"#;
+        assert!(compile(wat).is_err());
+        // exceptions
+        assert!(compile(r#"(module (tag $e) (func (throw $e)))"#).is_err());
+    }
+
+    #[test]
+    fn test_fuel_limit() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        let mut fuel = FuelUsage::default();
+        assert_eq!(executor.fuel_limit(&fuel), 10_000);
+
+        fuel.unlockers.push(10_000);
+        fuel.operators.push(1_000);
+        assert_eq!(executor.fuel_limit(&fuel), 4_000);
+
+        fuel.operators.push(5_000);
+        assert_eq!(executor.fuel_limit(&fuel), 0);
+    }
+
+    #[test]
+    fn test_read_leaf_unlocker() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        let wat = r#"(module
+            (import "env" "read_leaf_unlocker_size" (func $size (result i32)))
+            (import "env" "read_leaf_unlocker" (func $read (param i32)))
+            (memory (export "memory") 1)
+            (func (export "_entry") (result i32)
+                (call $read (i32.const 16))
+                (if (i32.ne (call $size) (i32.const 3)) (then (return (i32.const 1))))
+                (i32.ne (i32.load8_u (i32.const 18)) (i32.const 9))))"#;
+
+        let outcome = run(&executor, wat, Some(vec![1, 2, 9])).unwrap();
+        assert_eq!(outcome, ScriptOutcome::Success);
+
+        // Copy would end past the last page of guest memory.
+        let wat = r#"(module
+            (import "env" "read_leaf_unlocker" (func $read (param i32)))
+            (memory (export "memory") 1)
+            (func (export "_entry") (result i32)
+                (call $read (i32.const 65534))
+                (i32.const 0)))"#;
+
+        let outcome = run(&executor, wat, Some(vec![1, 2, 9])).unwrap();
+        assert!(matches!(outcome, ScriptOutcome::Trapped(_)));
+
+        // Copies are charged per byte, a buffer larger than the budget runs out of fuel.
+        let outcome = run(&executor, wat, Some(vec![0; 20_000])).unwrap();
+        assert_eq!(outcome, ScriptOutcome::OutOfFuel);
+    }
+
+    #[tokio::test]
+    async fn test_validate_scripts() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        let script = |code: usize| {
+            let wat = format!(
+                r#"(module (func (export "_entry") (result i32) (i32.const {})))"#,
+                code
+            );
+            let code = wat::parse_str(wat).unwrap();
+
+            PreparedScript {
+                module: Module::from_binary(&executor.engine, &code).unwrap(),
+                witness: vec![],
+                args: vec![],
+            }
+        };
+
+        let transactions: Vec<_> = (0..8).map(transaction).collect();
+
+        let batch = transactions
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| PreparedTransaction::new(tx, (0..i % 3).map(|_| script(0)).collect()))
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let fuel_usages = executor.validate_scripts(batch).await.unwrap();
+        assert_eq!(fuel_usages.len(), 8);
+        for (i, fuel) in fuel_usages.iter().enumerate() {
+            assert_eq!(fuel.unlockers.len(), i % 3);
+        }
+
+        // the first failing transaction in batch order is reported
+        for _ in 0..4 {
+            let batch = transactions
+                .iter()
+                .enumerate()
+                .map(|(i, tx)| match i {
+                    2 | 5 => PreparedTransaction::new(tx, vec![script(0), script(i)]),
+                    _ => PreparedTransaction::new(tx, vec![script(0)]),
+                })
+                .collect::<Result<_>>()
+                .unwrap();
+
+            let err = executor.validate_scripts(batch).await.unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Script {
+                    txid,
+                    input: 1,
+                    source: ScriptError::Rejected(2),
+                } if txid == transactions[2].txid
+            ));
+        }
+    }
+
+    #[test]
+    fn test_operator_governed_leaves() {
+        let home = TempDir::new();
+        let executor = executor(&home);
+
+        let transaction = transaction(0);
+        let governed = GovernedLeaves {
+            inputs: vec![0, 2],
+            outputs: vec![],
+        };
+
+        // accepts when it governs inputs 0 and 2 and no output
+        let wat = r#"(module
+            (import "env" "read_operator_inputs_size" (func $inputs_size (result i32)))
+            (import "env" "read_operator_inputs" (func $inputs (param i32)))
+            (import "env" "read_operator_outputs_size" (func $outputs_size (result i32)))
+            (memory (export "memory") 1)
+            (func (export "_entry") (result i32)
+                (call $inputs (i32.const 0))
+                (if (i32.ne (call $inputs_size) (i32.const 8)) (then (return (i32.const 1))))
+                (if (i32.ne (call $outputs_size) (i32.const 0)) (then (return (i32.const 2))))
+                (i32.ne (i32.load8_u (i32.const 7)) (i32.const 2))))"#;
+        let code = wat::parse_str(wat).unwrap();
 
-        instance.run()?;
+        let mut fuel = FuelUsage::default();
+        executor
+            .validate_operator(
+                &mut fuel,
+                &code,
+                &LeafId::default(),
+                &governed,
+                &transaction,
+            )
+            .unwrap();
+        assert_eq!(fuel.operators.len(), 1);
 
-        Ok(())
+        let governed = GovernedLeaves {
+            inputs: vec![0],
+            outputs: vec![1],
+        };
+        let err = executor
+            .validate_operator(
+                &mut fuel,
+                &code,
+                &LeafId::default(),
+                &governed,
+                &transaction,
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Operator {
+                source: ScriptError::Rejected(1),
+                ..
+            }
+        ));
+        assert_eq!(err.code(), 406);
     }
 }
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use wasmtime::{Caller, Extern, Linker, Trap};
+
+use crate::executors::ExecutorStore;
+
+const HOST_MODULE: &str = "env";
+
+/// Register the host functions described in `docs/design.md`.
+///
+/// Every buffer is exposed as a pair: `*_size` returns the length in bytes, the reader
+/// copies the whole buffer into guest memory at `ptr`. A missing buffer has size 0.
+/// Copies are charged one unit of fuel per byte.
+pub(crate) fn add_to_linker(linker: &mut Linker<ExecutorStore>) -> Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_unsigned_transaction_size",
+        |caller: Caller<'_, ExecutorStore>| buffer_size(Some(&caller.data().unsigned)),
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_unsigned_transaction",
+        |caller: Caller<'_, ExecutorStore>, ptr: u32| {
+            copy_to_guest(caller, ptr, |store| Some(&store.unsigned))
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_leaf_unlocker_size",
+        |caller: Caller<'_, ExecutorStore>| buffer_size(caller.data().unlocker.as_ref()),
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_leaf_unlocker",
+        |caller: Caller<'_, ExecutorStore>, ptr: u32| {
+            copy_to_guest(caller, ptr, |store| store.unlocker.as_ref())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_script_args_size",
+        |caller: Caller<'_, ExecutorStore>| buffer_size(caller.data().args.as_ref()),
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_script_args",
+        |caller: Caller<'_, ExecutorStore>, ptr: u32| {
+            copy_to_guest(caller, ptr, |store| store.args.as_ref())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_operator_inputs_size",
+        |caller: Caller<'_, ExecutorStore>| buffer_size(caller.data().operator_inputs.as_ref()),
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_operator_inputs",
+        |caller: Caller<'_, ExecutorStore>, ptr: u32| {
+            copy_to_guest(caller, ptr, |store| store.operator_inputs.as_ref())
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_operator_outputs_size",
+        |caller: Caller<'_, ExecutorStore>| buffer_size(caller.data().operator_outputs.as_ref()),
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "read_operator_outputs",
+        |caller: Caller<'_, ExecutorStore>, ptr: u32| {
+            copy_to_guest(caller, ptr, |store| store.operator_outputs.as_ref())
+        },
+    )?;
+
+    Ok(())
+}
+
+fn buffer_size(buffer: Option<&Vec<u8>>) -> Result<u32> {
+    let len = buffer.map(|b| b.len()).unwrap_or_default();
+
+    Ok(u32::try_from(len)?)
+}
+
+fn copy_to_guest<F>(mut caller: Caller<'_, ExecutorStore>, ptr: u32, buffer: F) -> Result<()>
+where
+    F: Fn(&ExecutorStore) -> Option<&Vec<u8>>,
+{
+    let memory = match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => memory,
+        _ => return Err(anyhow::anyhow!("Guest module does not export memory")),
+    };
+
+    let len = buffer(caller.data()).map(|b| b.len()).unwrap_or_default();
+
+    let fuel = caller.get_fuel()?;
+    if fuel < len as u64 {
+        caller.set_fuel(0)?;
+        return Err(Trap::OutOfFuel.into());
+    }
+    caller.set_fuel(fuel - len as u64)?;
+
+    let (memory, store) = memory.data_and_store_mut(&mut caller);
+
+    let Some(buffer) = buffer(store) else {
+        return Ok(());
+    };
+
+    let begin = ptr as usize;
+    let end = begin
+        .checked_add(buffer.len())
+        .filter(|end| *end <= memory.len())
+        .ok_or(anyhow::anyhow!(
+            "Guest memory out of bounds: ptr {}, len {}, memory size {}",
+            ptr,
+            buffer.len(),
+            memory.len()
+        ))?;
+
+    memory[begin..end].copy_from_slice(buffer);
+
+    Ok(())
+}
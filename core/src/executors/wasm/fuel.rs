@@ -0,0 +1,14 @@
+/// Fuel consumed by the scripts of a single transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FuelUsage {
+    /// Fuel used by each unlocker, in input order.
+    pub unlockers: Vec<u64>,
+    /// Fuel used by each operator, in execution order.
+    pub operators: Vec<u64>,
+}
+
+impl FuelUsage {
+    pub fn total(&self) -> u64 {
+        self.unlockers.iter().chain(&self.operators).sum()
+    }
+}
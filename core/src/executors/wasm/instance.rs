@@ -1,53 +1,143 @@
 use anyhow::Result;
 use bbm_primitives::UnsignedTransaction;
-use wasmtime::{Engine, Instance, Module, Store};
+use wasmtime::{Engine, Instance, Linker, Module, Store, Trap};
+
+use crate::{
+    GovernedLeaves,
+    executors::{CALL_DEPTH_EXPORT, LimitError, ScriptOutcome, WasmLimits},
+};
 
 pub(crate) struct ExecutorStore {
     pub unsigned: Vec<u8>,
     pub unlocker: Option<Vec<u8>>,
     pub args: Option<Vec<u8>>,
+    /// Governed input indices, big-endian u32 each, only set for operators.
+    pub operator_inputs: Option<Vec<u8>>,
+    /// Governed output indices, big-endian u32 each, only set for operators.
+    pub operator_outputs: Option<Vec<u8>>,
+    pub limits: WasmLimits,
+}
+
+impl ExecutorStore {
+    pub fn new(
+        unsigned: &UnsignedTransaction,
+        unlocker: Option<Vec<u8>>,
+        args: Option<Vec<u8>>,
+        limits: WasmLimits,
+    ) -> crate::Result<Self> {
+        Ok(Self::with_encoded(
+            unsigned.to_vec()?,
+            unlocker,
+            args,
+            limits,
+        ))
+    }
+
+    /// Like `new`, with the transaction already encoded.
+    pub fn with_encoded(
+        unsigned: Vec<u8>,
+        unlocker: Option<Vec<u8>>,
+        args: Option<Vec<u8>>,
+        limits: WasmLimits,
+    ) -> Self {
+        Self {
+            unsigned,
+            unlocker,
+            args,
+            operator_inputs: None,
+            operator_outputs: None,
+            limits,
+        }
+    }
+
+    pub fn with_governed(mut self, governed: &GovernedLeaves) -> Self {
+        let encode = |indices: &[u32]| indices.iter().flat_map(|i| i.to_be_bytes()).collect();
+
+        self.operator_inputs = Some(encode(&governed.inputs));
+        self.operator_outputs = Some(encode(&governed.outputs));
+        self
+    }
 }
 
 pub(crate) struct WasmInstance {
     instance: Instance,
     store: Store<ExecutorStore>,
+    fuel_limit: u64,
 }
 
 impl WasmInstance {
     pub fn new(
         engine: &Engine,
-        code: Vec<u8>,
-        args: Option<Vec<u8>>,
-        unsigned: &UnsignedTransaction,
-        unlocker: Option<Vec<u8>>,
+        linker: &Linker<ExecutorStore>,
+        module: &Module,
+        data: ExecutorStore,
+        fuel_limit: u64,
     ) -> Result<Self> {
-        let unsigned = unsigned.to_vec()?;
-
-        let mut store = Store::new(
-            engine,
-            ExecutorStore {
-                unsigned,
-                unlocker,
-                args,
-            },
-        );
+        // a store only ever holds the instance of the script, check the cap here as wasmtime
+        // reports it as a plain instantiation error
+        let limits = data.limits;
+        if limits.max_instances < 1 {
+            return Err(LimitError::Instances {
+                requested: 1,
+                limit: limits.max_instances,
+            }
+            .into());
+        }
 
-        let module = Module::from_binary(engine, &code)?;
+        let mut store = Store::new(engine, data);
 
-        let imports = &[];
+        store.limiter(|store| &mut store.limits);
+        store.set_fuel(fuel_limit)?;
 
-        let instance = Instance::new(&mut store, &module, imports)?;
+        let instance = linker.instantiate(&mut store, module)?;
 
-        Ok(Self { instance, store })
+        Ok(Self {
+            instance,
+            store,
+            fuel_limit,
+        })
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        let func = self
-            .instance
-            .get_typed_func::<(), u32>(&mut self.store, "_entry")?;
+    pub fn run(&mut self) -> Result<ScriptOutcome> {
+        let Some(func) = self.instance.get_func(&mut self.store, "_entry") else {
+            return Ok(ScriptOutcome::MissingEntry);
+        };
+
+        let func = func.typed::<(), u32>(&self.store)?;
+
+        let outcome = match func.call(&mut self.store, ()) {
+            Ok(0) => ScriptOutcome::Success,
+            Ok(code) => ScriptOutcome::Failed(code),
+            Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                ScriptOutcome::OutOfFuel
+            }
+            Err(_) if self.call_depth() > self.store.data().limits.max_call_depth => {
+                ScriptOutcome::LimitExceeded(LimitError::Stack)
+            }
+            // the native stack is only a safety net, `max_call_depth` should be hit first
+            Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::StackOverflow) => {
+                ScriptOutcome::LimitExceeded(LimitError::Stack)
+            }
+            Err(e) => match LimitError::find(&e) {
+                Some(limit) => ScriptOutcome::LimitExceeded(limit),
+                None => ScriptOutcome::Trapped(e.root_cause().to_string()),
+            },
+        };
+
+        Ok(outcome)
+    }
+
+    // depth reached by the script, left past the limit when `limit_call_depth` stopped it
+    fn call_depth(&mut self) -> u32 {
+        self.instance
+            .get_global(&mut self.store, CALL_DEPTH_EXPORT)
+            .and_then(|global| global.get(&mut self.store).i32())
+            .map_or(0, |depth| depth as u32)
+    }
 
-        func.call(&mut self.store, ())?;
+    pub fn fuel_consumed(&self) -> u64 {
+        let remaining = self.store.get_fuel().unwrap_or_default();
 
-        Ok(())
+        self.fuel_limit.saturating_sub(remaining)
     }
 }
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use wasmtime::ResourceLimiter;
+
+const WASM_PAGE_SIZE: usize = 0x10000;
+
+/// A store tried to grow past its `WasmLimits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LimitError {
+    #[error("memory limit exceeded: {requested} pages requested, limit is {limit}")]
+    Memory { requested: usize, limit: u32 },
+
+    #[error("table limit exceeded: {requested} elements requested, limit is {limit}")]
+    Table { requested: usize, limit: u32 },
+
+    #[error("instance limit exceeded: {requested} instances requested, limit is {limit}")]
+    Instances { requested: usize, limit: usize },
+
+    #[error("stack limit exceeded")]
+    Stack,
+}
+
+impl LimitError {
+    /// The limit error behind `error`, if a limit is what made it fail.
+    pub fn find(error: &anyhow::Error) -> Option<Self> {
+        error
+            .chain()
+            .find_map(|e| e.downcast_ref::<Self>())
+            .copied()
+    }
+}
+
+/// Resource caps applied to every store running a script.
+#[derive(Debug, Clone, Copy)]
+pub struct WasmLimits {
+    pub max_memory_pages: u32,
+    pub max_table_elements: u32,
+    pub max_instances: usize,
+    /// Nested calls a script may make, enforced by `limit_call_depth`.
+    pub max_call_depth: u32,
+}
+
+impl ResourceLimiter for WasmLimits {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        let max_memory = self.max_memory_pages as usize * WASM_PAGE_SIZE;
+
+        if desired > max_memory {
+            return Err(LimitError::Memory {
+                requested: desired.div_ceil(WASM_PAGE_SIZE),
+                limit: self.max_memory_pages,
+            }
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if desired > self.max_table_elements as usize {
+            return Err(LimitError::Table {
+                requested: desired,
+                limit: self.max_table_elements,
+            }
+            .into());
+        }
+
+        Ok(true)
+    }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+    }
+}
@@ -1,7 +1,25 @@
 mod instance;
 pub(crate) use instance::*;
 
+mod ffi;
+
+mod depth;
+pub(crate) use depth::*;
+
+mod outcome;
+pub use outcome::*;
+
+mod limits;
+pub use limits::*;
+
+mod fuel;
+pub use fuel::*;
+
+mod cache;
+pub use cache::*;
+
+mod prepared;
+pub use prepared::*;
+
 mod executor;
 pub use executor::*;
-
-// TODO: cache wasm module by leaf id
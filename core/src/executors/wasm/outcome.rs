@@ -0,0 +1,29 @@
+use crate::{
+    Error,
+    executors::{FuelUsage, LimitError},
+};
+
+/// Verdict of running a script's `_entry` export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOutcome {
+    /// `_entry` returned status 0.
+    Success,
+    /// `_entry` returned a non-zero status.
+    Failed(u32),
+    /// Execution aborted before `_entry` returned.
+    Trapped(String),
+    /// The fuel budget ran out before `_entry` returned.
+    OutOfFuel,
+    /// A memory or table grew past its limit.
+    LimitExceeded(LimitError),
+    /// The module does not export `_entry`.
+    MissingEntry,
+}
+
+/// Result of running the unlockers of one transaction.
+#[derive(Debug, Default)]
+pub struct ScriptReport {
+    pub fuel: FuelUsage,
+    /// Input index of the unlocker that failed, with its error.
+    pub failure: Option<(usize, Error)>,
+}
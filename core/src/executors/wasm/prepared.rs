@@ -0,0 +1,29 @@
+use bbm_primitives::{FilledTransaction, Txid};
+use wasmtime::Module;
+
+use crate::Result;
+
+/// Unlock script of one input with its code resolved, ready to run on any thread.
+pub struct PreparedScript {
+    pub(crate) module: Module,
+    pub(crate) witness: Vec<u8>,
+    pub(crate) args: Vec<u8>,
+}
+
+/// Prepared scripts of one transaction, owning everything they need to run.
+pub struct PreparedTransaction {
+    pub(crate) txid: Txid,
+    pub(crate) unsigned: Vec<u8>,
+    pub(crate) scripts: Vec<PreparedScript>,
+}
+
+impl PreparedTransaction {
+    /// `scripts` must hold the prepared script of every input of `transaction`, in order.
+    pub fn new(transaction: &FilledTransaction, scripts: Vec<PreparedScript>) -> Result<Self> {
+        Ok(Self {
+            txid: transaction.txid.clone(),
+            unsigned: transaction.unsigned.to_vec()?,
+            scripts,
+        })
+    }
+}
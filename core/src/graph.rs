@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bbm_primitives::{FixedBytes, OutPoint, Transaction, Txid, UnlockScript};
+
+use crate::{Error, Result};
+
+/// Dependency graph of a batch of transactions.
+///
+/// A transaction is the parent of every transaction in the batch spending one of its
+/// outputs, unlocking an input with code it creates, or placing outputs under an operator
+/// it creates. Nodes are identified by their position in the batch.
+pub struct TxGraph {
+    transactions: Vec<Transaction>,
+    txids: Vec<Txid>,
+    parents: Vec<BTreeSet<usize>>,
+    children: Vec<BTreeSet<usize>>,
+    layers: Vec<Vec<usize>>,
+    cyclic: Vec<usize>,
+}
+
+impl TxGraph {
+    /// Build the graph, failing if two transactions conflict or the batch contains a cycle.
+    ///
+    /// Transactions conflict when they share a txid or spend the same leaf.
+    pub fn new(transactions: Vec<Transaction>) -> Result<Self> {
+        Self::build(transactions, false)
+    }
+
+    /// Build the graph of a batch where several transactions may spend the same leaf.
+    ///
+    /// Which spender wins is left to the caller, sharing a txid still fails. Unlockers are not
+    /// covered by the txid, so their code leaves may form a cycle: nodes on a cycle and their
+    /// descendants are left out of `layers` and reported by `cyclic`.
+    pub fn with_conflicts(transactions: Vec<Transaction>) -> Result<Self> {
+        Self::build(transactions, true)
+    }
+
+    fn build(transactions: Vec<Transaction>, allow_conflicts: bool) -> Result<Self> {
+        let len = transactions.len();
+
+        let mut txids = Vec::with_capacity(len);
+        let mut seen = BTreeSet::new();
+        let mut producers = BTreeMap::new();
+
+        for (node, transaction) in transactions.iter().enumerate() {
+            let txid = transaction.unsigned.hash()?;
+
+            if !seen.insert(txid.clone()) {
+                return Err(Error::DuplicateTransaction(txid));
+            }
+
+            for i in 0..transaction.unsigned.outputs.len() {
+                let leaf_id = OutPoint {
+                    txid: txid.clone(),
+                    index: i as u32,
+                }
+                .leaf_id();
+
+                producers.insert(leaf_id, node);
+            }
+
+            txids.push(txid);
+        }
+
+        let mut parents = vec![BTreeSet::new(); len];
+        let mut children = vec![BTreeSet::new(); len];
+        let mut spenders = BTreeMap::new();
+
+        for (node, transaction) in transactions.iter().enumerate() {
+            for leaf_id in &transaction.unsigned.inputs {
+                if let Some(other) = spenders.insert(leaf_id, node)
+                    && !allow_conflicts
+                {
+                    return Err(Error::ConflictingSpend {
+                        leaf_id: leaf_id.clone(),
+                        first: txids[other].clone(),
+                        second: txids[node].clone(),
+                    });
+                }
+
+                if let Some(&parent) = producers.get(leaf_id) {
+                    parents[node].insert(parent);
+                    children[parent].insert(node);
+                }
+            }
+
+            // unlockers running code deployed in the batch, malformed ones fail their script
+            for unlocker in &transaction.unlockers {
+                if let Ok(script) = UnlockScript::from_slice(&unlocker.0)
+                    && let Some(&parent) = producers.get(&FixedBytes(script.code_leaf))
+                    && parent != node
+                {
+                    parents[node].insert(parent);
+                    children[parent].insert(node);
+                }
+            }
+
+            // outputs placed under an operator deployed in the batch
+            for leaf in &transaction.unsigned.outputs {
+                if let Some(operator) = &leaf.operator
+                    && let Some(&parent) = producers.get(operator)
+                {
+                    parents[node].insert(parent);
+                    children[parent].insert(node);
+                }
+            }
+        }
+
+        let layers = layers(&parents, &children);
+
+        let mut cyclic: BTreeSet<usize> = (0..len).collect();
+        for node in layers.iter().flatten() {
+            cyclic.remove(node);
+        }
+        if !cyclic.is_empty() && !allow_conflicts {
+            return Err(Error::DependencyCycle);
+        }
+
+        Ok(Self {
+            transactions,
+            txids,
+            parents,
+            children,
+            layers,
+            cyclic: cyclic.into_iter().collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn txid(&self, node: usize) -> &Txid {
+        &self.txids[node]
+    }
+
+    pub fn parents(&self, node: usize) -> &BTreeSet<usize> {
+        &self.parents[node]
+    }
+
+    pub fn children(&self, node: usize) -> &BTreeSet<usize> {
+        &self.children[node]
+    }
+
+    /// Nodes grouped so that every parent sits in an earlier layer than its children.
+    ///
+    /// Transactions of one layer do not depend on each other and can be validated
+    /// concurrently. Nodes of a layer keep their batch order.
+    pub fn layers(&self) -> &[Vec<usize>] {
+        &self.layers
+    }
+
+    /// Nodes that cannot be ordered, as they are part of or depend on a cycle.
+    pub fn cyclic(&self) -> &[usize] {
+        &self.cyclic
+    }
+
+    /// Nodes in topological order, layer by layer.
+    pub fn order(&self) -> impl Iterator<Item = usize> + '_ {
+        self.layers.iter().flatten().copied()
+    }
+
+    /// Transactions in topological order, each with its position in the batch.
+    pub fn into_ordered(self) -> Vec<(usize, Transaction)> {
+        let order: Vec<usize> = self.order().collect();
+
+        let mut transactions: Vec<Option<Transaction>> =
+            self.transactions.into_iter().map(Some).collect();
+
+        order
+            .into_iter()
+            .filter_map(|node| Some((node, transactions[node].take()?)))
+            .collect()
+    }
+}
+
+/// Kahn's algorithm, nodes part of or depending on a cycle are left out.
+fn layers(parents: &[BTreeSet<usize>], children: &[BTreeSet<usize>]) -> Vec<Vec<usize>> {
+    let mut in_degree: Vec<usize> = parents.iter().map(|p| p.len()).collect();
+
+    let mut layers = Vec::new();
+    let mut layer: Vec<usize> = (0..parents.len())
+        .filter(|node| in_degree[*node] == 0)
+        .collect();
+
+    while !layer.is_empty() {
+        let mut next = BTreeSet::new();
+
+        for node in &layer {
+            for child in &children[*node] {
+                in_degree[*child] -= 1;
+
+                if in_degree[*child] == 0 {
+                    next.insert(*child);
+                }
+            }
+        }
+
+        layers.push(layer);
+        layer = next.into_iter().collect();
+    }
+
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::FixedBytes;
+
+    use super::*;
+    use crate::test_utils::{output, transaction, unlock_script, unlocker};
+
+    #[test]
+    fn test_layers() {
+        let stored = FixedBytes([9u8; 32]);
+
+        let a = transaction(1, vec![stored]);
+        let b = transaction(2, vec![output(&a)]);
+        let c = transaction(3, vec![output(&b)]);
+        let d = transaction(4, vec![]);
+
+        // children first, the graph restores the order
+        let graph = TxGraph::new(vec![c, b, d, a]).unwrap();
+
+        assert_eq!(graph.layers(), &[vec![2, 3], vec![1], vec![0]]);
+        assert_eq!(graph.parents(0), &BTreeSet::from([1]));
+        assert_eq!(graph.children(3), &BTreeSet::from([1]));
+
+        let nonces: Vec<u64> = graph
+            .into_ordered()
+            .into_iter()
+            .map(|(_, tx)| tx.unsigned.nonce)
+            .collect();
+        assert_eq!(nonces, vec![4, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_code_leaf() {
+        let deploy = transaction(1, vec![]);
+
+        let mut spend = transaction(2, vec![FixedBytes([9u8; 32])]);
+        spend.unlockers[0] = unlocker(&unlock_script(&output(&deploy)));
+
+        let graph = TxGraph::new(vec![spend, deploy]).unwrap();
+        assert_eq!(graph.parents(0), &BTreeSet::from([1]));
+        assert_eq!(graph.layers(), &[vec![1], vec![0]]);
+
+        // unlockers running each other's outputs
+        let mut a = transaction(1, vec![FixedBytes([8u8; 32])]);
+        let mut b = transaction(2, vec![FixedBytes([9u8; 32])]);
+        a.unlockers[0] = unlocker(&unlock_script(&output(&b)));
+        b.unlockers[0] = unlocker(&unlock_script(&output(&a)));
+        let err = TxGraph::new(vec![a, b]).err().unwrap();
+        assert!(matches!(err, Error::DependencyCycle));
+
+        let mut a = transaction(1, vec![FixedBytes([8u8; 32])]);
+        let mut b = transaction(2, vec![FixedBytes([9u8; 32])]);
+        a.unlockers[0] = unlocker(&unlock_script(&output(&b)));
+        b.unlockers[0] = unlocker(&unlock_script(&output(&a)));
+        let c = transaction(3, vec![output(&a)]);
+        let d = transaction(4, vec![]);
+        let graph = TxGraph::with_conflicts(vec![a, b, c, d]).unwrap();
+        assert_eq!(graph.layers(), &[vec![3]]);
+        assert_eq!(graph.cyclic(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let stored = FixedBytes([9u8; 32]);
+
+        let a = transaction(1, vec![stored.clone()]);
+        let b = transaction(2, vec![stored.clone()]);
+        let err = TxGraph::new(vec![a, b]).err().unwrap();
+        assert!(matches!(err, Error::ConflictingSpend { .. }));
+
+        let a = transaction(1, vec![stored.clone()]);
+        let b = transaction(2, vec![stored]);
+        let graph = TxGraph::with_conflicts(vec![a, b]).unwrap();
+        assert_eq!(graph.layers(), &[vec![0, 1]]);
+
+        let a = transaction(1, vec![]);
+        let b = transaction(1, vec![]);
+        let err = TxGraph::new(vec![a, b]).err().unwrap();
+        assert_eq!(err.code(), 101);
+    }
+}
@@ -6,8 +6,25 @@ pub use runtime::*;
 
 pub mod executors;
 
+pub mod storages;
+
 mod config;
 pub use config::*;
 
 mod checker;
 pub use checker::*;
+
+mod graph;
+pub use graph::*;
+
+mod receipt;
+pub use receipt::*;
+
+mod operator;
+pub use operator::*;
+
+mod error;
+pub use error::*;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use bbm_primitives::{FilledTransaction, LeafId};
+
+/// Inputs and outputs of a transaction governed by one operator, by ascending index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GovernedLeaves {
+    pub inputs: Vec<u32>,
+    pub outputs: Vec<u32>,
+}
+
+/// Distinct operators of a transaction in execution order, which is by operator leaf id.
+///
+/// An operator governs every input and output naming it, and runs once per transaction.
+pub fn transaction_operators(filled_tx: &FilledTransaction) -> BTreeMap<LeafId, GovernedLeaves> {
+    let mut operators: BTreeMap<LeafId, GovernedLeaves> = BTreeMap::new();
+
+    for (i, leaf) in filled_tx.inputs.iter().enumerate() {
+        if let Some(operator) = &leaf.operator {
+            let governed = operators.entry(operator.clone()).or_default();
+            governed.inputs.push(i as u32);
+        }
+    }
+
+    for (i, leaf) in filled_tx.unsigned.outputs.iter().enumerate() {
+        if let Some(operator) = &leaf.operator {
+            let governed = operators.entry(operator.clone()).or_default();
+            governed.outputs.push(i as u32);
+        }
+    }
+
+    operators
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::{Bytes, FixedBytes, Leaf, UnsignedTransaction};
+
+    use super::*;
+
+    fn leaf(operator: Option<u8>) -> Leaf {
+        Leaf {
+            version: 1,
+            nonce: 0,
+            owner: FixedBytes([1u8; 20]),
+            index: FixedBytes([2u8; 32]),
+            operator: operator.map(|id| FixedBytes([id; 32])),
+            data: Bytes(vec![]),
+        }
+    }
+
+    #[test]
+    fn test_transaction_operators() {
+        let filled_tx = FilledTransaction {
+            txid: FixedBytes([0u8; 32]),
+            unsigned: UnsignedTransaction {
+                version: 1,
+                nonce: 0,
+                inputs: vec![FixedBytes([3u8; 32]); 3],
+                outputs: vec![leaf(Some(5)), leaf(None), leaf(Some(4))],
+            },
+            inputs: vec![leaf(Some(5)), leaf(Some(4)), leaf(Some(5))],
+            unlockers: vec![],
+        };
+
+        let operators = transaction_operators(&filled_tx);
+
+        let expected = BTreeMap::from([
+            (
+                FixedBytes([4u8; 32]),
+                GovernedLeaves {
+                    inputs: vec![1],
+                    outputs: vec![2],
+                },
+            ),
+            (
+                FixedBytes([5u8; 32]),
+                GovernedLeaves {
+                    inputs: vec![0, 2],
+                    outputs: vec![0],
+                },
+            ),
+        ]);
+        assert_eq!(operators, expected);
+    }
+}
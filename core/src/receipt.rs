@@ -0,0 +1,77 @@
+use bbm_primitives::Txid;
+
+use crate::executors::FuelUsage;
+
+/// Outcome of one transaction of a batch executed with receipts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub txid: Txid,
+    pub status: ReceiptStatus,
+    /// Why the transaction was not applied.
+    pub error: Option<ReceiptErrorKind>,
+    /// Unlocker or operator that rejected the transaction.
+    pub failed_at: Option<FailedAt>,
+    pub fuel: FuelUsage,
+}
+
+impl Receipt {
+    pub(crate) fn new(txid: Txid) -> Self {
+        Self {
+            txid,
+            status: ReceiptStatus::Applied,
+            error: None,
+            failed_at: None,
+            fuel: FuelUsage::default(),
+        }
+    }
+
+    pub(crate) fn reject(&mut self, error: ReceiptErrorKind, failed_at: Option<FailedAt>) {
+        self.status = ReceiptStatus::Rejected;
+        self.error = Some(error);
+        self.failed_at = failed_at;
+    }
+
+    pub(crate) fn skip(&mut self) {
+        self.status = ReceiptStatus::Skipped;
+        self.error = Some(ReceiptErrorKind::Dependency);
+    }
+
+    pub fn is_applied(&self) -> bool {
+        matches!(self.status, ReceiptStatus::Applied)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptStatus {
+    /// Inputs spent and outputs stored.
+    Applied,
+    /// The transaction itself is invalid.
+    Rejected,
+    /// A transaction it depends on was not applied.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptErrorKind {
+    /// The transaction cannot be encoded.
+    Malformed,
+    /// Same txid as an earlier transaction of the batch, or spends a leaf already spent by a
+    /// valid transaction executed before it.
+    Conflict,
+    /// Unlocker count mismatch, or an input or operator leaf is missing or already spent.
+    Inputs,
+    /// An unlocker failed.
+    Script,
+    /// An operator failed.
+    Operator,
+    /// A parent transaction was not applied.
+    Dependency,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailedAt {
+    /// Index in the transaction inputs.
+    Input(usize),
+    /// Index in the operators of the transaction, in execution order.
+    Operator(usize),
+}
@@ -1,38 +1,601 @@
-use anyhow::Result;
-use bbm_primitives::Transaction;
+use std::collections::BTreeSet;
 
-use crate::{Storage, TransactionChecker};
+use bbm_primitives::{FilledTransaction, LeafId, OutPoint, Transaction, Txid};
+
+use crate::{
+    CommittableStorage, Error, FailedAt, LeafStorage, Receipt, ReceiptErrorKind, Result, Storage,
+    TransactionChecker, TxGraph,
+    executors::{FuelUsage, PreparedTransaction, WasmExecutor},
+    transaction_operators,
+};
 
 pub struct Runtime<S> {
     storage: S,
+    executor: WasmExecutor,
 }
 
 impl<S> Runtime<S>
 where
     S: Storage,
 {
-    pub fn new(storage: S) -> Self {
-        Self { storage }
+    pub fn new(storage: S, executor: WasmExecutor) -> Self {
+        Self { storage, executor }
     }
 
-    pub async fn batch_execute_transaction(&self, transactions: Vec<Transaction>) -> Result<()> {
+    pub async fn batch_execute_transaction(
+        &self,
+        version: u64,
+        transactions: Vec<Transaction>,
+    ) -> Result<Vec<FuelUsage>> {
         let leaf_storage = self.storage.open_leaf_storage()?;
 
         let mut checker = TransactionChecker::default();
 
-        // check leaf_id
+        let graph = TxGraph::new(transactions)?;
+        let batch_len = graph.len();
+
+        // check leaf_id, parents before children
+        let mut positions = Vec::new();
         let mut filled_txs = Vec::new();
-        for transaction in transactions {
+        for (position, transaction) in graph.into_ordered() {
             let filled_transaction = checker.check_leaf_id(&leaf_storage, transaction).await?;
             filled_txs.push(filled_transaction);
+            positions.push(position);
         }
 
-        // check scripts
+        // check scripts, resolved here and run on the executor's worker threads
+        let mut scripts = Vec::new();
+        for filled_tx in &filled_txs {
+            let mut prepared = Vec::new();
+            for input in 0..filled_tx.inputs.len() {
+                let script = self
+                    .executor
+                    .prepare_script(&leaf_storage, &checker, filled_tx, input)
+                    .await?;
+                prepared.push(script);
+            }
+
+            scripts.push(PreparedTransaction::new(filled_tx, prepared)?);
+        }
+
+        let mut fuel_usages = self.executor.validate_scripts(scripts).await?;
 
-        // check operators
+        // check operators, each distinct operator runs once per transaction
+        for (filled_tx, fuel) in filled_txs.iter().zip(&mut fuel_usages) {
+            for (operator_leaf_id, governed) in &transaction_operators(filled_tx) {
+                let operator = checker.get_operator(operator_leaf_id).ok_or_else(|| {
+                    Error::OperatorNotFound {
+                        txid: filled_tx.txid.clone(),
+                        leaf_id: operator_leaf_id.clone(),
+                    }
+                })?;
+
+                self.executor.validate_operator(
+                    fuel,
+                    &operator.data.0,
+                    operator_leaf_id,
+                    governed,
+                    filled_tx,
+                )?;
+            }
+        }
 
         // append all leafs and mark spent
+        let mut spent_leaf_ids = Vec::new();
+        for filled_tx in filled_txs {
+            apply(&leaf_storage, filled_tx, &mut spent_leaf_ids).await?;
+        }
+
+        leaf_storage.commit(version)?;
+
+        // spent code and operator leaves are rejected before their module is looked up, so
+        // their cached modules are dead
+        for leaf_id in &spent_leaf_ids {
+            self.executor.evict_module(leaf_id);
+        }
+
+        // report fuel in batch order
+        let mut batch_fuel_usages = vec![FuelUsage::default(); batch_len];
+        for (position, fuel) in positions.into_iter().zip(fuel_usages) {
+            batch_fuel_usages[position] = fuel;
+        }
+
+        Ok(batch_fuel_usages)
+    }
+
+    /// Execute a batch from an untrusted source, reporting each transaction in a `Receipt`.
+    ///
+    /// Invalid transactions are rejected and every transaction depending on them is skipped,
+    /// the rest is applied and committed. When several valid transactions spend the same
+    /// leaf, the first one in execution order wins and the others are rejected. Errors are
+    /// only returned when the batch cannot be executed at all, e.g. on storage failures.
+    pub async fn batch_execute_transaction_with_receipts(
+        &self,
+        version: u64,
+        transactions: Vec<Transaction>,
+    ) -> Result<Vec<Receipt>> {
+        let leaf_storage = self.storage.open_leaf_storage()?;
+
+        let mut checker = TransactionChecker::default();
+
+        // drop repeated transactions, spenders of a same leaf are settled once validated
+        let mut receipts = Vec::with_capacity(transactions.len());
+        let mut positions = Vec::new();
+        let mut kept = Vec::new();
+        let mut txids = BTreeSet::new();
+
+        for (position, transaction) in transactions.into_iter().enumerate() {
+            let Ok(txid) = transaction.unsigned.hash() else {
+                let mut receipt = Receipt::new(Txid::default());
+                receipt.reject(ReceiptErrorKind::Malformed, None);
+                receipts.push(receipt);
+                continue;
+            };
+
+            let mut receipt = Receipt::new(txid.clone());
+
+            if txids.insert(txid) {
+                positions.push(position);
+                kept.push(transaction);
+            } else {
+                receipt.reject(ReceiptErrorKind::Conflict, None);
+            }
+
+            receipts.push(receipt);
+        }
+
+        let graph = TxGraph::with_conflicts(kept)?;
+        for node in graph.cyclic() {
+            receipts[positions[*node]].skip();
+        }
+
+        let parents: Vec<Vec<usize>> = (0..graph.len())
+            .map(|node| graph.parents(node).iter().map(|p| positions[*p]).collect())
+            .collect();
+
+        // check leaf_id, parents before children
+        let mut filled_txs = Vec::new();
+        for (node, transaction) in graph.into_ordered() {
+            let position = positions[node];
+
+            if !parents_applied(&receipts, &parents[node]) {
+                receipts[position].skip();
+                continue;
+            }
+
+            match checker.fill_leaf_id(&leaf_storage, transaction).await {
+                Ok(filled_tx) => filled_txs.push((position, &parents[node], filled_tx)),
+                Err(e @ Error::Database(_)) => return Err(e),
+                Err(_) => receipts[position].reject(ReceiptErrorKind::Inputs, None),
+            }
+        }
+
+        // check scripts
+        let mut scripts = Vec::new();
+        let mut script_positions = Vec::new();
+        for (position, _, filled_tx) in &filled_txs {
+            let mut prepared = Vec::new();
+            for input in 0..filled_tx.inputs.len() {
+                match self
+                    .executor
+                    .prepare_script(&leaf_storage, &checker, filled_tx, input)
+                    .await
+                {
+                    Ok(script) => prepared.push(script),
+                    Err(Error::Script { .. }) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if prepared.len() < filled_tx.inputs.len() {
+                let failed_at = FailedAt::Input(prepared.len());
+                receipts[*position].reject(ReceiptErrorKind::Script, Some(failed_at));
+                continue;
+            }
+
+            scripts.push(PreparedTransaction::new(filled_tx, prepared)?);
+            script_positions.push(*position);
+        }
+
+        let reports = self.executor.run_scripts(scripts).await?;
+        for (position, report) in script_positions.into_iter().zip(reports) {
+            let receipt = &mut receipts[position];
+            receipt.fuel = report.fuel;
+
+            if let Some((input, _)) = report.failure {
+                receipt.reject(ReceiptErrorKind::Script, Some(FailedAt::Input(input)));
+            }
+        }
+
+        // check operators, parents before children
+        for (position, parents, filled_tx) in &filled_txs {
+            if !receipts[*position].is_applied() {
+                continue;
+            }
+
+            if !parents_applied(&receipts, parents) {
+                receipts[*position].skip();
+                continue;
+            }
+
+            let receipt = &mut receipts[*position];
+            for (i, (operator_leaf_id, governed)) in
+                transaction_operators(filled_tx).iter().enumerate()
+            {
+                let operator = checker.get_operator(operator_leaf_id).ok_or_else(|| {
+                    Error::OperatorNotFound {
+                        txid: filled_tx.txid.clone(),
+                        leaf_id: operator_leaf_id.clone(),
+                    }
+                })?;
+
+                let result = self.executor.validate_operator(
+                    &mut receipt.fuel,
+                    &operator.data.0,
+                    operator_leaf_id,
+                    governed,
+                    filled_tx,
+                );
+
+                match result {
+                    Ok(()) => {}
+                    Err(Error::Operator { .. }) => {
+                        receipt.reject(ReceiptErrorKind::Operator, Some(FailedAt::Operator(i)));
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        // settle conflicts, parents before children: the first valid spender of a leaf wins
+        for (position, parents, filled_tx) in &filled_txs {
+            if !receipts[*position].is_applied() {
+                continue;
+            }
+
+            if !parents_applied(&receipts, parents) {
+                receipts[*position].skip();
+                continue;
+            }
+
+            if checker.claim_inputs(filled_tx).is_err() {
+                receipts[*position].reject(ReceiptErrorKind::Conflict, None);
+            }
+        }
+
+        // append leafs of applied transactions and mark spent
+        let mut spent_leaf_ids = Vec::new();
+        for (position, _, filled_tx) in filled_txs {
+            if receipts[position].is_applied() {
+                apply(&leaf_storage, filled_tx, &mut spent_leaf_ids).await?;
+            }
+        }
+
+        leaf_storage.commit(version)?;
+
+        // spent code and operator leaves are rejected before their module is looked up, so
+        // their cached modules are dead
+        for leaf_id in &spent_leaf_ids {
+            self.executor.evict_module(leaf_id);
+        }
+
+        Ok(receipts)
+    }
+}
+
+fn parents_applied(receipts: &[Receipt], parents: &[usize]) -> bool {
+    parents.iter().all(|parent| receipts[*parent].is_applied())
+}
+
+/// Mark the inputs of `filled_tx` as spent and store its outputs.
+async fn apply<L>(
+    leaf_storage: &L,
+    filled_tx: FilledTransaction,
+    spent_leaf_ids: &mut Vec<LeafId>,
+) -> Result<()>
+where
+    L: LeafStorage,
+{
+    for leaf_id in &filled_tx.unsigned.inputs {
+        leaf_storage.mark_leaf_as_spent(leaf_id).await?;
+    }
+    spent_leaf_ids.extend(filled_tx.unsigned.inputs);
+
+    for (i, leaf) in filled_tx.unsigned.outputs.into_iter().enumerate() {
+        let leaf_id = OutPoint {
+            txid: filled_tx.txid.clone(),
+            index: i as u32,
+        }
+        .leaf_id();
+
+        leaf_storage.store_leaf(&leaf_id, leaf).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::{Bytes, FixedBytes, Leaf, UnlockScript};
+
+    use super::*;
+    use crate::{
+        ReceiptStatus, ScriptError, StorageConfig,
+        storages::MemoryStorage,
+        test_utils::{TempDir, executor, leaf, output, transaction, unlock_script, unlocker},
+    };
+
+    const ACCEPT: &str = r#"(module (func (export "_entry") (result i32) (i32.const 0)))"#;
+
+    fn runtime(home: &TempDir) -> Runtime<MemoryStorage> {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 1,
+        });
+
+        Runtime::new(storage, executor(home))
+    }
+
+    /// Commit `leaves` as version 1.
+    async fn store(runtime: &Runtime<MemoryStorage>, leaves: Vec<(LeafId, Leaf)>) {
+        let leaf_storage = runtime.storage.open_leaf_storage().unwrap();
+        for (leaf_id, leaf) in leaves {
+            leaf_storage.store_leaf(&leaf_id, leaf).await.unwrap();
+        }
+        leaf_storage.commit(1).unwrap();
+    }
+
+    /// Store a code leaf accepting everything and a leaf owned by its script, return the
+    /// script and the owned leaf id.
+    async fn store_owned(runtime: &Runtime<MemoryStorage>) -> (UnlockScript, LeafId) {
+        let code = Leaf {
+            operator: None,
+            data: Bytes(wat::parse_str(ACCEPT).unwrap()),
+            ..leaf(1, 0)
+        };
+        let script = unlock_script(&FixedBytes([1u8; 32]));
+        let owned = Leaf {
+            owner: script.address().unwrap(),
+            operator: None,
+            ..leaf(2, 0)
+        };
+
+        store(
+            runtime,
+            vec![
+                (FixedBytes([1u8; 32]), code),
+                (FixedBytes([2u8; 32]), owned),
+            ],
+        )
+        .await;
+
+        (script, FixedBytes([2u8; 32]))
+    }
+
+    #[tokio::test]
+    async fn test_receipts() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+
+        let missing = transaction(1, vec![FixedBytes([9u8; 32])]);
+        let child = transaction(2, vec![output(&missing)]);
+        let valid = transaction(3, vec![]);
+        let duplicate = transaction(3, vec![]);
+        let valid_output = output(&valid);
+
+        let receipts = runtime
+            .batch_execute_transaction_with_receipts(1, vec![child, missing, valid, duplicate])
+            .await
+            .unwrap();
+
+        let statuses: Vec<_> = receipts.iter().map(|r| (r.status, r.error)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (ReceiptStatus::Skipped, Some(ReceiptErrorKind::Dependency)),
+                (ReceiptStatus::Rejected, Some(ReceiptErrorKind::Inputs)),
+                (ReceiptStatus::Applied, None),
+                (ReceiptStatus::Rejected, Some(ReceiptErrorKind::Conflict)),
+            ]
+        );
+
+        let leaf_storage = runtime.storage.open_leaf_storage().unwrap();
+        assert!(
+            leaf_storage
+                .get_leaf(&valid_output)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(runtime.storage.version(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_operator_from_batch() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+
+        // deploys an operator accepting everything
+        let mut deploy = transaction(1, vec![]);
+        deploy.unsigned.outputs[0].data = Bytes(wat::parse_str(ACCEPT).unwrap());
+
+        let mut governed = transaction(2, vec![]);
+        governed.unsigned.outputs[0].operator = Some(output(&deploy));
+
+        let receipts = runtime
+            .batch_execute_transaction_with_receipts(1, vec![governed, deploy])
+            .await
+            .unwrap();
+
+        assert!(receipts.iter().all(|r| r.is_applied()));
+        assert_eq!(receipts[0].fuel.operators.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_code_from_rejected_transaction() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+
+        // deploys code but fails its own unlocker
+        let mut deploy = transaction(1, vec![FixedBytes([1u8; 32])]);
+        deploy.unlockers[0] = Bytes(vec![0xde, 0xad]);
+        deploy.unsigned.outputs[0].data = Bytes(wat::parse_str(ACCEPT).unwrap());
+
+        let script = unlock_script(&output(&deploy));
+        let free = Leaf {
+            operator: None,
+            ..leaf(1, 0)
+        };
+        let owned = Leaf {
+            owner: script.address().unwrap(),
+            ..free.clone()
+        };
+        store(
+            &runtime,
+            vec![
+                (FixedBytes([1u8; 32]), free),
+                (FixedBytes([2u8; 32]), owned),
+            ],
+        )
+        .await;
+
+        let mut spend = transaction(2, vec![FixedBytes([2u8; 32])]);
+        spend.unlockers[0] = unlocker(&script);
+
+        let receipts = runtime
+            .batch_execute_transaction_with_receipts(2, vec![spend, deploy])
+            .await
+            .unwrap();
+
+        let statuses: Vec<_> = receipts.iter().map(|r| (r.status, r.error)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (ReceiptStatus::Skipped, Some(ReceiptErrorKind::Dependency)),
+                (ReceiptStatus::Rejected, Some(ReceiptErrorKind::Script)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_first_valid_spender_wins() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+        let (script, owned) = store_owned(&runtime).await;
+
+        let mut invalid = transaction(1, vec![owned.clone()]);
+        invalid.unlockers[0] = Bytes(vec![0xde, 0xad]);
+        let mut valid = transaction(2, vec![owned.clone()]);
+        valid.unlockers[0] = unlocker(&script);
+        let mut late = transaction(3, vec![owned.clone()]);
+        late.unlockers[0] = unlocker(&script);
+        let valid_output = output(&valid);
+
+        let receipts = runtime
+            .batch_execute_transaction_with_receipts(2, vec![invalid, valid, late])
+            .await
+            .unwrap();
+
+        let statuses: Vec<_> = receipts.iter().map(|r| (r.status, r.error)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (ReceiptStatus::Rejected, Some(ReceiptErrorKind::Script)),
+                (ReceiptStatus::Applied, None),
+                (ReceiptStatus::Rejected, Some(ReceiptErrorKind::Conflict)),
+            ]
+        );
+
+        let leaf_storage = runtime.storage.open_leaf_storage().unwrap();
+        assert!(leaf_storage.is_spent(&owned).await.unwrap());
+        assert!(
+            leaf_storage
+                .get_leaf(&valid_output)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spent_code_leaf() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+        let (script, owned) = store_owned(&runtime).await;
+
+        let code = FixedBytes(script.code_leaf);
+        let leaf_storage = runtime.storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&code).await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        let mut spend = transaction(1, vec![owned]);
+        spend.unlockers[0] = unlocker(&script);
+
+        let err = runtime
+            .batch_execute_transaction(3, vec![spend])
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            Error::Script { input: 0, source: ScriptError::CodeSpent(leaf_id), .. } if leaf_id == code
+        ));
+        assert_eq!(runtime.storage.version(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_child_of_script_rejected() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+        let (script, owned) = store_owned(&runtime).await;
+
+        // the child alone is valid, its parent fails its unlocker
+        let mut parent = transaction(1, vec![owned]);
+        parent.unlockers[0] = Bytes(vec![0xde, 0xad]);
+        parent.unsigned.outputs[0].owner = script.address().unwrap();
+        let mut child = transaction(2, vec![output(&parent)]);
+        child.unlockers[0] = unlocker(&script);
+
+        let receipts = runtime
+            .batch_execute_transaction_with_receipts(2, vec![child, parent])
+            .await
+            .unwrap();
+
+        let statuses: Vec<_> = receipts.iter().map(|r| (r.status, r.error)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (ReceiptStatus::Skipped, Some(ReceiptErrorKind::Dependency)),
+                (ReceiptStatus::Rejected, Some(ReceiptErrorKind::Script)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_code_leaf_cycle() {
+        let home = TempDir::new();
+        let runtime = runtime(&home);
+
+        // unlockers are not part of the txid, each runs the code deployed by the other
+        let mut a = transaction(1, vec![FixedBytes([8u8; 32])]);
+        let mut b = transaction(2, vec![FixedBytes([9u8; 32])]);
+        a.unlockers[0] = unlocker(&unlock_script(&output(&b)));
+        b.unlockers[0] = unlocker(&unlock_script(&output(&a)));
+        let valid = transaction(3, vec![]);
+
+        let receipts = runtime
+            .batch_execute_transaction_with_receipts(1, vec![a, b, valid])
+            .await
+            .unwrap();
 
-        Ok(())
+        let statuses: Vec<_> = receipts.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ReceiptStatus::Skipped,
+                ReceiptStatus::Skipped,
+                ReceiptStatus::Applied
+            ]
+        );
     }
 }
@@ -0,0 +1,654 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use async_trait::async_trait;
+use bbm_primitives::{IndexKey, Leaf, LeafId, LeafParser, LeafWithId};
+use redb::{
+    Database, MultimapTableDefinition, ReadTransaction, ReadableDatabase, ReadableTable,
+    ReadableTableMetadata, TableDefinition, WriteTransaction,
+};
+
+use super::{PendingChanges, Rewind, UndoLog, undo_path};
+use crate::{CommittableStorage, Error, LeafSnapshot, LeafStorage, Result, Storage, StorageConfig};
+
+/// leaf id -> encoded leaf
+const LEAVES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("leaves");
+/// leaf id of every spent leaf not yet purged
+const SPENT: TableDefinition<&[u8], ()> = TableDefinition::new("spent");
+/// index key -> leaf ids
+const INDEX: MultimapTableDefinition<&[u8], &[u8]> = MultimapTableDefinition::new("index");
+/// committed version -> encoded undo log of that commit
+const UNDO: TableDefinition<u64, &[u8]> = TableDefinition::new("undo");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+const VERSION_KEY: &str = "version";
+
+/// `Storage` persisted in a redb database file.
+///
+/// A commit is a single redb write transaction, so it is applied atomically and survives a
+/// crash once `commit` returns. Undo journals of the last `retained_versions` commits are
+/// stored alongside the leaves.
+#[derive(Clone)]
+pub struct DiskStorage {
+    db: Arc<Database>,
+    retained_versions: usize,
+}
+
+impl DiskStorage {
+    pub fn open(path: impl AsRef<Path>, config: &StorageConfig) -> Result<Self> {
+        let db = Database::create(path)?;
+
+        let txn = db.begin_write()?;
+        txn.open_table(LEAVES)?;
+        txn.open_table(SPENT)?;
+        txn.open_multimap_table(INDEX)?;
+        txn.open_table(UNDO)?;
+        txn.open_table(META)?;
+        txn.commit()?;
+
+        Ok(Self {
+            db: Arc::new(db),
+            retained_versions: config.retained_versions,
+        })
+    }
+
+    pub fn version(&self) -> Result<u64> {
+        let txn = self.db.begin_read()?;
+        let meta = txn.open_table(META)?;
+
+        Ok(meta
+            .get(VERSION_KEY)?
+            .map(|v| v.value())
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl Storage for DiskStorage {
+    type LeafStorage = DiskLeafStorage;
+
+    type Snapshot = DiskSnapshot;
+
+    async fn revert_to_version(&self, version: u64) -> Result<()> {
+        let txn = self.db.begin_write()?;
+
+        {
+            let mut meta = txn.open_table(META)?;
+            let mut undo = txn.open_table(UNDO)?;
+
+            let current = meta
+                .get(VERSION_KEY)?
+                .map(|v| v.value())
+                .unwrap_or_default();
+
+            let versions = undo_path(current, version, |v| {
+                undo.get(v)?
+                    .map(|bytes| Ok(UndoLog::from_slice(bytes.value())?.prev_version))
+                    .transpose()
+            })?;
+
+            for v in versions {
+                let log = match undo.remove(v)? {
+                    Some(bytes) => UndoLog::from_slice(bytes.value())?,
+                    None => continue,
+                };
+
+                undo_commit(&txn, log)?;
+            }
+
+            meta.insert(VERSION_KEY, version)?;
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    fn open_leaf_storage(&self) -> Result<Self::LeafStorage> {
+        Ok(DiskLeafStorage {
+            db: self.db.clone(),
+            retained_versions: self.retained_versions,
+            pending: Mutex::new(PendingChanges::default()),
+        })
+    }
+
+    fn snapshot(&self, version: u64) -> Result<Self::Snapshot> {
+        // a redb read transaction keeps seeing the database as it was when it began
+        let txn = self.db.begin_read()?;
+
+        let mut logs = Vec::new();
+
+        {
+            let meta = txn.open_table(META)?;
+            let undo = txn.open_table(UNDO)?;
+
+            let current = meta
+                .get(VERSION_KEY)?
+                .map(|v| v.value())
+                .unwrap_or_default();
+
+            undo_path(current, version, |v| {
+                let Some(bytes) = undo.get(v)? else {
+                    return Ok(None);
+                };
+
+                let log = UndoLog::from_slice(bytes.value())?;
+                let prev_version = log.prev_version;
+                logs.push(log);
+
+                Ok(Some(prev_version))
+            })?;
+        }
+
+        Ok(DiskSnapshot {
+            txn,
+            rewind: Rewind::new(logs),
+        })
+    }
+}
+
+/// `LeafSnapshot` of a `DiskStorage`, backed by a redb read transaction.
+pub struct DiskSnapshot {
+    txn: ReadTransaction,
+    rewind: Rewind,
+}
+
+#[async_trait]
+impl LeafSnapshot for DiskSnapshot {
+    async fn get_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>> {
+        let leaf = read_leaf(&self.txn, leaf_id)?;
+
+        Ok(self.rewind.leaf(leaf_id, leaf))
+    }
+
+    async fn get_leaf_by_index_key(&self, index_key: &IndexKey) -> Result<Vec<LeafWithId>> {
+        let leaves_table = self.txn.open_table(LEAVES)?;
+        let spent_table = self.txn.open_table(SPENT)?;
+        let index = self.txn.open_multimap_table(INDEX)?;
+
+        let mut leaves = BTreeMap::new();
+
+        for leaf_id in index.get(index_key.0.as_slice())? {
+            let leaf_id = leaf_id?;
+            let leaf_id = leaf_id.value();
+
+            let spent = spent_table.get(leaf_id)?.is_some();
+
+            if let Some(bytes) = leaves_table.get(leaf_id)? {
+                let leaf = decode_leaf(bytes.value())?;
+                leaves.insert(LeafId::from_slice(leaf_id)?, (leaf, spent));
+            }
+        }
+
+        for (leaf_id, leaf) in self.rewind.purged_by_index_key(index_key) {
+            leaves.insert(leaf_id.clone(), (leaf.clone(), false));
+        }
+
+        let leaves = leaves
+            .into_iter()
+            .filter(|(leaf_id, (_, spent))| {
+                !self.rewind.is_created(leaf_id) && !self.rewind.is_spent(leaf_id, *spent)
+            })
+            .map(|(leaf_id, (leaf, _))| LeafWithId { leaf_id, leaf })
+            .collect();
+
+        Ok(leaves)
+    }
+}
+
+/// Write buffer over a `DiskStorage`, changes are written to disk on `commit`.
+pub struct DiskLeafStorage {
+    db: Arc<Database>,
+    retained_versions: usize,
+    pending: Mutex<PendingChanges>,
+}
+
+impl DiskLeafStorage {
+    fn pending(&self) -> MutexGuard<'_, PendingChanges> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>> {
+        read_leaf(&self.db.begin_read()?, leaf_id)
+    }
+}
+
+impl CommittableStorage for DiskLeafStorage {
+    fn commit(self, version: u64) -> Result<()> {
+        let pending = self
+            .pending
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let txn = self.db.begin_write()?;
+
+        let mut log = {
+            let mut meta = txn.open_table(META)?;
+            let current = meta
+                .get(VERSION_KEY)?
+                .map(|v| v.value())
+                .unwrap_or_default();
+
+            if version <= current {
+                return Err(Error::VersionNotIncreasing { version, current });
+            }
+
+            meta.insert(VERSION_KEY, version)?;
+
+            UndoLog::new(current)
+        };
+
+        {
+            let mut leaves = txn.open_table(LEAVES)?;
+            let mut index = txn.open_multimap_table(INDEX)?;
+
+            for (leaf_id, leaf) in &pending.leaves {
+                leaves.insert(leaf_id.0.as_slice(), encode_leaf(leaf)?.as_slice())?;
+                index.insert(leaf.index.0.as_slice(), leaf_id.0.as_slice())?;
+
+                log.created.push(leaf_id.clone());
+                log.index_added.push((leaf.index.clone(), leaf_id.clone()));
+            }
+
+            let mut spent = txn.open_table(SPENT)?;
+            for leaf_id in &pending.spent {
+                if spent.insert(leaf_id.0.as_slice(), ())?.is_none() {
+                    log.spent.push(leaf_id.clone());
+                }
+            }
+        }
+
+        if pending.purge_spent {
+            log.purged = purge_spent(&txn)?;
+        }
+
+        {
+            let mut undo = txn.open_table(UNDO)?;
+            undo.insert(version, log.to_vec()?.as_slice())?;
+
+            while undo.len()? > self.retained_versions as u64 {
+                undo.pop_first()?;
+            }
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LeafStorage for DiskLeafStorage {
+    async fn store_leaf(&self, leaf_id: &LeafId, leaf: Leaf) -> Result<()> {
+        self.pending().leaves.insert(leaf_id.clone(), leaf);
+
+        Ok(())
+    }
+
+    async fn get_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>> {
+        if let Some(leaf) = self.pending().leaves.get(leaf_id) {
+            return Ok(Some(leaf.clone()));
+        }
+
+        self.read_leaf(leaf_id)
+    }
+
+    async fn get_leaf_by_index_key(&self, index_key: &IndexKey) -> Result<Vec<LeafWithId>> {
+        let pending = self.pending();
+
+        let txn = self.db.begin_read()?;
+        let leaves_table = txn.open_table(LEAVES)?;
+        let spent_table = txn.open_table(SPENT)?;
+        let index = txn.open_multimap_table(INDEX)?;
+
+        let mut leaves = BTreeMap::new();
+
+        for leaf_id in index.get(index_key.0.as_slice())? {
+            let leaf_id = leaf_id?;
+            let leaf_id = leaf_id.value();
+
+            if spent_table.get(leaf_id)?.is_some() {
+                continue;
+            }
+
+            if let Some(bytes) = leaves_table.get(leaf_id)? {
+                leaves.insert(LeafId::from_slice(leaf_id)?, decode_leaf(bytes.value())?);
+            }
+        }
+
+        for (leaf_id, leaf) in &pending.leaves {
+            if &leaf.index == index_key {
+                leaves.insert(leaf_id.clone(), leaf.clone());
+            }
+        }
+
+        let leaves = leaves
+            .into_iter()
+            .filter(|(leaf_id, _)| !pending.spent.contains(leaf_id))
+            .map(|(leaf_id, leaf)| LeafWithId { leaf_id, leaf })
+            .collect();
+
+        Ok(leaves)
+    }
+
+    async fn mark_leaf_as_spent(&self, leaf_id: &LeafId) -> Result<()> {
+        let mut pending = self.pending();
+
+        if !pending.leaves.contains_key(leaf_id) && self.read_leaf(leaf_id)?.is_none() {
+            return Err(Error::SpentLeafNotFound(leaf_id.clone()));
+        }
+
+        pending.spent.insert(leaf_id.clone());
+
+        Ok(())
+    }
+
+    async fn is_spent(&self, leaf_id: &LeafId) -> Result<bool> {
+        if self.pending().spent.contains(leaf_id) {
+            return Ok(true);
+        }
+
+        let txn = self.db.begin_read()?;
+        let spent = txn.open_table(SPENT)?;
+
+        Ok(spent.get(leaf_id.0.as_slice())?.is_some())
+    }
+
+    async fn purge_spent_leaves(&self) -> Result<()> {
+        self.pending().purge_spent = true;
+
+        Ok(())
+    }
+}
+
+/// Drop every spent leaf, returning the dropped leaves.
+fn purge_spent(txn: &WriteTransaction) -> Result<Vec<LeafWithId>> {
+    let mut leaves = txn.open_table(LEAVES)?;
+    let mut spent = txn.open_table(SPENT)?;
+    let mut index = txn.open_multimap_table(INDEX)?;
+
+    let mut spent_leaf_ids = Vec::new();
+    for entry in spent.iter()? {
+        let (leaf_id, _) = entry?;
+        spent_leaf_ids.push(leaf_id.value().to_vec());
+    }
+
+    let mut purged = Vec::new();
+
+    for leaf_id in &spent_leaf_ids {
+        let leaf = leaves
+            .remove(leaf_id.as_slice())?
+            .map(|bytes| decode_leaf(bytes.value()))
+            .transpose()?;
+
+        if let Some(leaf) = leaf {
+            index.remove(leaf.index.0.as_slice(), leaf_id.as_slice())?;
+            purged.push(LeafWithId {
+                leaf_id: LeafId::from_slice(leaf_id)?,
+                leaf,
+            });
+        }
+
+        spent.remove(leaf_id.as_slice())?;
+    }
+
+    Ok(purged)
+}
+
+fn undo_commit(txn: &WriteTransaction, log: UndoLog) -> Result<()> {
+    let mut leaves = txn.open_table(LEAVES)?;
+    let mut spent = txn.open_table(SPENT)?;
+    let mut index = txn.open_multimap_table(INDEX)?;
+
+    for LeafWithId { leaf_id, leaf } in &log.purged {
+        leaves.insert(leaf_id.0.as_slice(), encode_leaf(leaf)?.as_slice())?;
+        index.insert(leaf.index.0.as_slice(), leaf_id.0.as_slice())?;
+        spent.insert(leaf_id.0.as_slice(), ())?;
+    }
+
+    for leaf_id in &log.spent {
+        spent.remove(leaf_id.0.as_slice())?;
+    }
+
+    for (index_key, leaf_id) in &log.index_added {
+        index.remove(index_key.0.as_slice(), leaf_id.0.as_slice())?;
+    }
+
+    for leaf_id in &log.created {
+        leaves.remove(leaf_id.0.as_slice())?;
+    }
+
+    Ok(())
+}
+
+fn read_leaf(txn: &ReadTransaction, leaf_id: &LeafId) -> Result<Option<Leaf>> {
+    let leaves = txn.open_table(LEAVES)?;
+
+    leaves
+        .get(leaf_id.0.as_slice())?
+        .map(|bytes| decode_leaf(bytes.value()))
+        .transpose()
+}
+
+fn encode_leaf(leaf: &Leaf) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    leaf.append_to_vec(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+fn decode_leaf(bytes: &[u8]) -> Result<Leaf> {
+    Ok(LeafParser::new(bytes)?.to_leaf()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::FixedBytes;
+
+    use super::*;
+    use crate::test_utils::{TempDir, leaf, leaf_id};
+
+    fn config() -> StorageConfig {
+        StorageConfig {
+            retained_versions: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_survives_restart() {
+        let home = TempDir::new();
+        let path = home.path().join("restart.redb");
+
+        {
+            let storage = DiskStorage::open(&path, &config()).unwrap();
+
+            let leaf_storage = storage.open_leaf_storage().unwrap();
+            leaf_storage
+                .store_leaf(&leaf_id(1), leaf(7, 1))
+                .await
+                .unwrap();
+            leaf_storage
+                .store_leaf(&leaf_id(2), leaf(7, 2))
+                .await
+                .unwrap();
+            leaf_storage.commit(1).unwrap();
+
+            let leaf_storage = storage.open_leaf_storage().unwrap();
+            leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+            leaf_storage.commit(2).unwrap();
+
+            // never committed
+            let leaf_storage = storage.open_leaf_storage().unwrap();
+            leaf_storage
+                .store_leaf(&leaf_id(3), leaf(7, 3))
+                .await
+                .unwrap();
+        }
+
+        let storage = DiskStorage::open(&path, &config()).unwrap();
+        assert_eq!(storage.version().unwrap(), 2);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert!(leaf_storage.is_spent(&leaf_id(1)).await.unwrap());
+        assert!(!leaf_storage.is_spent(&leaf_id(2)).await.unwrap());
+        assert_eq!(
+            leaf_storage.get_leaf(&leaf_id(1)).await.unwrap(),
+            Some(leaf(7, 1))
+        );
+        assert_eq!(leaf_storage.get_leaf(&leaf_id(3)).await.unwrap(), None);
+
+        let leaves = leaf_storage
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        assert_eq!(
+            leaves,
+            vec![LeafWithId {
+                leaf_id: leaf_id(2),
+                leaf: leaf(7, 2)
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_and_version_check() {
+        let home = TempDir::new();
+        let storage = DiskStorage::open(home.path().join("purge.redb"), &config()).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(1), leaf(7, 1))
+            .await
+            .unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+        leaf_storage.purge_spent_leaves().await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert_eq!(leaf_storage.get_leaf(&leaf_id(1)).await.unwrap(), None);
+
+        // a rejected commit leaves the database untouched
+        leaf_storage
+            .store_leaf(&leaf_id(2), leaf(7, 2))
+            .await
+            .unwrap();
+        assert!(leaf_storage.commit(2).is_err());
+        assert_eq!(storage.version().unwrap(), 2);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert_eq!(leaf_storage.get_leaf(&leaf_id(2)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_revert_after_restart() {
+        let home = TempDir::new();
+        let path = home.path().join("revert.redb");
+
+        {
+            let storage = DiskStorage::open(&path, &config()).unwrap();
+
+            let leaf_storage = storage.open_leaf_storage().unwrap();
+            leaf_storage
+                .store_leaf(&leaf_id(1), leaf(7, 1))
+                .await
+                .unwrap();
+            leaf_storage.commit(1).unwrap();
+
+            let leaf_storage = storage.open_leaf_storage().unwrap();
+            leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+            leaf_storage
+                .store_leaf(&leaf_id(2), leaf(7, 2))
+                .await
+                .unwrap();
+            leaf_storage.purge_spent_leaves().await.unwrap();
+            leaf_storage.commit(2).unwrap();
+        }
+
+        let storage = DiskStorage::open(&path, &config()).unwrap();
+        storage.revert_to_version(1).await.unwrap();
+        assert_eq!(storage.version().unwrap(), 1);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert_eq!(leaf_storage.get_leaf(&leaf_id(2)).await.unwrap(), None);
+
+        let leaves = leaf_storage
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        assert_eq!(
+            leaves,
+            vec![LeafWithId {
+                leaf_id: leaf_id(1),
+                leaf: leaf(7, 1)
+            }]
+        );
+
+        // only the undo journals of the last two commits are kept
+        leaf_storage.commit(2).unwrap();
+        storage.open_leaf_storage().unwrap().commit(3).unwrap();
+        assert!(storage.revert_to_version(0).await.is_err());
+        assert_eq!(storage.version().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let home = TempDir::new();
+        let storage = DiskStorage::open(home.path().join("snapshot.redb"), &config()).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(1), leaf(7, 1))
+            .await
+            .unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(2), leaf(7, 2))
+            .await
+            .unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let pinned = storage.snapshot(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(3), leaf(7, 3))
+            .await
+            .unwrap();
+        leaf_storage.purge_spent_leaves().await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        // taken before and after the commit, both see version 1
+        for snapshot in [pinned, storage.snapshot(1).unwrap()] {
+            assert_eq!(
+                snapshot.get_leaf(&leaf_id(1)).await.unwrap(),
+                Some(leaf(7, 1))
+            );
+            assert_eq!(snapshot.get_leaf(&leaf_id(3)).await.unwrap(), None);
+
+            let leaves = snapshot
+                .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+                .await
+                .unwrap();
+            let leaf_ids: Vec<_> = leaves.into_iter().map(|l| l.leaf_id).collect();
+            assert_eq!(leaf_ids, vec![leaf_id(1), leaf_id(2)]);
+        }
+
+        let snapshot = storage.snapshot(2).unwrap();
+        assert_eq!(snapshot.get_leaf(&leaf_id(1)).await.unwrap(), None);
+
+        let leaves = snapshot
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        let leaf_ids: Vec<_> = leaves.into_iter().map(|l| l.leaf_id).collect();
+        assert_eq!(leaf_ids, vec![leaf_id(2), leaf_id(3)]);
+
+        assert!(storage.snapshot(3).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bbm_primitives::{IndexKey, Leaf, LeafId, LeafParser, LeafWithId};
+
+use crate::{Error, Result};
+
+/// Undo journal of one commit, enough to restore the leaf set of the version before it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct UndoLog {
+    /// Version the storage was at before this commit.
+    pub prev_version: u64,
+    /// Leaves created by this commit.
+    pub created: Vec<LeafId>,
+    /// Leaves marked as spent by this commit, excluding leaves that were already spent.
+    pub spent: Vec<LeafId>,
+    /// Index entries added by this commit.
+    pub index_added: Vec<(IndexKey, LeafId)>,
+    /// Spent leaves dropped by a purge in this commit.
+    pub purged: Vec<LeafWithId>,
+}
+
+impl UndoLog {
+    pub fn new(prev_version: u64) -> Self {
+        Self {
+            prev_version,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        let mut v = Vec::new();
+
+        v.extend_from_slice(&self.prev_version.to_be_bytes());
+
+        v.extend_from_slice(&(self.created.len() as u32).to_be_bytes());
+        for leaf_id in &self.created {
+            v.extend_from_slice(&leaf_id.0);
+        }
+
+        v.extend_from_slice(&(self.spent.len() as u32).to_be_bytes());
+        for leaf_id in &self.spent {
+            v.extend_from_slice(&leaf_id.0);
+        }
+
+        v.extend_from_slice(&(self.index_added.len() as u32).to_be_bytes());
+        for (index_key, leaf_id) in &self.index_added {
+            v.extend_from_slice(&index_key.0);
+            v.extend_from_slice(&leaf_id.0);
+        }
+
+        v.extend_from_slice(&(self.purged.len() as u32).to_be_bytes());
+        for purged in &self.purged {
+            v.extend_from_slice(&purged.leaf_id.0);
+            purged.leaf.append_to_vec(&mut v)?;
+        }
+
+        Ok(v)
+    }
+
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        let mut reader = Reader(slice);
+
+        let mut log = Self::new(u64::from_be_bytes(reader.take()?));
+
+        for _ in 0..reader.count()? {
+            log.created.push(LeafId::from_slice(&reader.take::<32>()?)?);
+        }
+
+        for _ in 0..reader.count()? {
+            log.spent.push(LeafId::from_slice(&reader.take::<32>()?)?);
+        }
+
+        for _ in 0..reader.count()? {
+            let index_key = IndexKey::from_slice(&reader.take::<32>()?)?;
+            let leaf_id = LeafId::from_slice(&reader.take::<32>()?)?;
+            log.index_added.push((index_key, leaf_id));
+        }
+
+        for _ in 0..reader.count()? {
+            let leaf_id = LeafId::from_slice(&reader.take::<32>()?)?;
+
+            let parser = LeafParser::new(reader.0)?;
+            let leaf = parser.to_leaf()?;
+            reader.0 = &reader.0[parser.leaf_len()..];
+
+            log.purged.push(LeafWithId { leaf_id, leaf });
+        }
+
+        if !reader.0.is_empty() {
+            return Err(Error::UndoLogTrailingBytes(reader.0.len()));
+        }
+
+        Ok(log)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let Some((head, rest)) = self.0.split_first_chunk::<N>() else {
+            return Err(Error::UndoLogTruncated);
+        };
+
+        self.0 = rest;
+
+        Ok(*head)
+    }
+
+    fn count(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+}
+
+/// Versions to undo, newest first, to go from `current` back to `target`.
+///
+/// `prev_version` looks up the version before a committed one in the journal.
+pub(crate) fn undo_path<F>(current: u64, target: u64, mut prev_version: F) -> Result<Vec<u64>>
+where
+    F: FnMut(u64) -> Result<Option<u64>>,
+{
+    let mut versions = Vec::new();
+    let mut version = current;
+
+    while version > target {
+        let Some(prev) = prev_version(version)? else {
+            return Err(Error::VersionNotRetained { version, target });
+        };
+
+        versions.push(version);
+        version = prev;
+    }
+
+    if version != target {
+        return Err(Error::VersionNotFound(target));
+    }
+
+    Ok(versions)
+}
+
+/// Overlay rewinding a committed state to an older version without modifying it.
+///
+/// Built from the undo logs of every commit after that version.
+#[derive(Default)]
+pub(crate) struct Rewind {
+    created: BTreeSet<LeafId>,
+    spent: BTreeSet<LeafId>,
+    purged: BTreeMap<LeafId, Leaf>,
+}
+
+impl Rewind {
+    pub fn new(logs: impl IntoIterator<Item = UndoLog>) -> Self {
+        let mut rewind = Self::default();
+
+        for log in logs {
+            rewind.created.extend(log.created);
+            rewind.spent.extend(log.spent);
+            rewind.purged.extend(
+                log.purged
+                    .into_iter()
+                    .map(|purged| (purged.leaf_id, purged.leaf)),
+            );
+        }
+
+        rewind
+    }
+
+    /// Whether the leaf was created after the rewound version.
+    pub fn is_created(&self, leaf_id: &LeafId) -> bool {
+        self.created.contains(leaf_id)
+    }
+
+    /// Leaf as of the rewound version, `current` is its committed value.
+    pub fn leaf(&self, leaf_id: &LeafId, current: Option<Leaf>) -> Option<Leaf> {
+        if self.is_created(leaf_id) {
+            return None;
+        }
+
+        self.purged.get(leaf_id).cloned().or(current)
+    }
+
+    /// Spent flag as of the rewound version, `current` is its committed value.
+    pub fn is_spent(&self, leaf_id: &LeafId, current: bool) -> bool {
+        if self.spent.contains(leaf_id) {
+            return false;
+        }
+
+        // a leaf is only purged once spent
+        self.purged.contains_key(leaf_id) || current
+    }
+
+    /// Purged leaves stored under `index_key`, which are not in the committed index.
+    pub fn purged_by_index_key<'a>(
+        &'a self,
+        index_key: &'a IndexKey,
+    ) -> impl Iterator<Item = (&'a LeafId, &'a Leaf)> {
+        self.purged
+            .iter()
+            .filter(move |(_, leaf)| &leaf.index == index_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::{Bytes, FixedBytes};
+
+    use super::*;
+
+    #[test]
+    fn test_undo_log_encoding() {
+        let log = UndoLog {
+            prev_version: 3,
+            created: vec![FixedBytes([1u8; 32]), FixedBytes([2u8; 32])],
+            spent: vec![FixedBytes([3u8; 32])],
+            index_added: vec![(FixedBytes([7u8; 32]), FixedBytes([1u8; 32]))],
+            purged: vec![LeafWithId {
+                leaf_id: FixedBytes([4u8; 32]),
+                leaf: Leaf {
+                    version: 1,
+                    nonce: 2,
+                    owner: FixedBytes([5u8; 20]),
+                    index: FixedBytes([7u8; 32]),
+                    operator: None,
+                    data: Bytes(vec![1, 2, 3]),
+                },
+            }],
+        };
+
+        let bytes = log.to_vec().unwrap();
+        assert_eq!(UndoLog::from_slice(&bytes).unwrap(), log);
+
+        assert!(UndoLog::from_slice(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,581 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use async_trait::async_trait;
+use bbm_primitives::{IndexKey, Leaf, LeafId, LeafWithId};
+
+use super::{PendingChanges, Rewind, UndoLog, undo_path};
+use crate::{CommittableStorage, Error, LeafSnapshot, LeafStorage, Result, Storage, StorageConfig};
+
+#[derive(Clone, Default)]
+struct MemoryState {
+    version: u64,
+    leaves: BTreeMap<LeafId, Leaf>,
+    spent: BTreeSet<LeafId>,
+    index: BTreeMap<IndexKey, BTreeSet<LeafId>>,
+}
+
+impl MemoryState {
+    fn insert_leaf(&mut self, leaf_id: LeafId, leaf: Leaf) {
+        self.index
+            .entry(leaf.index.clone())
+            .or_default()
+            .insert(leaf_id.clone());
+        self.leaves.insert(leaf_id, leaf);
+    }
+
+    fn remove_leaf(&mut self, leaf_id: &LeafId) -> Option<Leaf> {
+        let leaf = self.leaves.remove(leaf_id)?;
+
+        self.remove_index(&leaf.index, leaf_id);
+        self.spent.remove(leaf_id);
+
+        Some(leaf)
+    }
+
+    fn remove_index(&mut self, index_key: &IndexKey, leaf_id: &LeafId) {
+        if let Some(leaf_ids) = self.index.get_mut(index_key) {
+            leaf_ids.remove(leaf_id);
+
+            if leaf_ids.is_empty() {
+                self.index.remove(index_key);
+            }
+        }
+    }
+
+    fn undo(&mut self, log: UndoLog) {
+        for LeafWithId { leaf_id, leaf } in log.purged {
+            self.spent.insert(leaf_id.clone());
+            self.insert_leaf(leaf_id, leaf);
+        }
+
+        for leaf_id in &log.spent {
+            self.spent.remove(leaf_id);
+        }
+
+        for (index_key, leaf_id) in &log.index_added {
+            self.remove_index(index_key, leaf_id);
+        }
+
+        for leaf_id in &log.created {
+            self.leaves.remove(leaf_id);
+        }
+
+        self.version = log.prev_version;
+    }
+}
+
+struct MemoryInner {
+    // shared with snapshots, copied on the next commit while any is alive
+    state: Arc<MemoryState>,
+    // undo journal of the most recent commits, by committed version
+    journal: BTreeMap<u64, UndoLog>,
+    retained_versions: usize,
+}
+
+/// Reference `Storage` keeping every leaf in memory.
+///
+/// Each commit records an undo journal, so the last `retained_versions` versions can be
+/// reverted to.
+#[derive(Clone)]
+pub struct MemoryStorage {
+    inner: Arc<RwLock<MemoryInner>>,
+}
+
+impl MemoryStorage {
+    pub fn new(config: &StorageConfig) -> Self {
+        let inner = MemoryInner {
+            state: Arc::default(),
+            journal: BTreeMap::new(),
+            retained_versions: config.retained_versions,
+        };
+
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.read().state.version
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, MemoryInner> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MemoryInner> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    type LeafStorage = MemoryLeafStorage;
+
+    type Snapshot = MemorySnapshot;
+
+    async fn revert_to_version(&self, version: u64) -> Result<()> {
+        let mut inner = self.write();
+
+        let versions = undo_path(inner.state.version, version, |v| {
+            Ok(inner.journal.get(&v).map(|log| log.prev_version))
+        })?;
+
+        for v in versions {
+            if let Some(log) = inner.journal.remove(&v) {
+                Arc::make_mut(&mut inner.state).undo(log);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_leaf_storage(&self) -> Result<Self::LeafStorage> {
+        Ok(MemoryLeafStorage {
+            storage: self.clone(),
+            pending: Mutex::new(PendingChanges::default()),
+        })
+    }
+
+    fn snapshot(&self, version: u64) -> Result<Self::Snapshot> {
+        let inner = self.read();
+
+        let versions = undo_path(inner.state.version, version, |v| {
+            Ok(inner.journal.get(&v).map(|log| log.prev_version))
+        })?;
+
+        let logs = versions
+            .iter()
+            .filter_map(|v| inner.journal.get(v).cloned());
+
+        Ok(MemorySnapshot {
+            state: inner.state.clone(),
+            rewind: Rewind::new(logs),
+        })
+    }
+}
+
+/// `LeafSnapshot` of a `MemoryStorage`.
+pub struct MemorySnapshot {
+    state: Arc<MemoryState>,
+    rewind: Rewind,
+}
+
+#[async_trait]
+impl LeafSnapshot for MemorySnapshot {
+    async fn get_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>> {
+        let leaf = self.state.leaves.get(leaf_id).cloned();
+
+        Ok(self.rewind.leaf(leaf_id, leaf))
+    }
+
+    async fn get_leaf_by_index_key(&self, index_key: &IndexKey) -> Result<Vec<LeafWithId>> {
+        let state = &self.state;
+
+        let mut leaves = BTreeMap::new();
+
+        if let Some(leaf_ids) = state.index.get(index_key) {
+            for leaf_id in leaf_ids {
+                if let Some(leaf) = state.leaves.get(leaf_id) {
+                    leaves.insert(leaf_id, leaf);
+                }
+            }
+        }
+
+        leaves.extend(self.rewind.purged_by_index_key(index_key));
+
+        let leaves = leaves
+            .into_iter()
+            .filter(|(leaf_id, _)| {
+                !self.rewind.is_created(leaf_id)
+                    && !self
+                        .rewind
+                        .is_spent(leaf_id, state.spent.contains(*leaf_id))
+            })
+            .map(|(leaf_id, leaf)| LeafWithId {
+                leaf_id: leaf_id.clone(),
+                leaf: leaf.clone(),
+            })
+            .collect();
+
+        Ok(leaves)
+    }
+}
+
+/// Write buffer over a `MemoryStorage`, changes become visible to others on `commit`.
+pub struct MemoryLeafStorage {
+    storage: MemoryStorage,
+    pending: Mutex<PendingChanges>,
+}
+
+impl MemoryLeafStorage {
+    fn pending(&self) -> MutexGuard<'_, PendingChanges> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl CommittableStorage for MemoryLeafStorage {
+    fn commit(self, version: u64) -> Result<()> {
+        let pending = self
+            .pending
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut inner = self.storage.write();
+
+        if version <= inner.state.version {
+            return Err(Error::VersionNotIncreasing {
+                version,
+                current: inner.state.version,
+            });
+        }
+
+        let state = Arc::make_mut(&mut inner.state);
+        let mut log = UndoLog::new(state.version);
+
+        for (leaf_id, leaf) in pending.leaves {
+            log.created.push(leaf_id.clone());
+            log.index_added.push((leaf.index.clone(), leaf_id.clone()));
+            state.insert_leaf(leaf_id, leaf);
+        }
+
+        for leaf_id in pending.spent {
+            if state.spent.insert(leaf_id.clone()) {
+                log.spent.push(leaf_id);
+            }
+        }
+
+        if pending.purge_spent {
+            for leaf_id in state.spent.clone() {
+                if let Some(leaf) = state.remove_leaf(&leaf_id) {
+                    log.purged.push(LeafWithId { leaf_id, leaf });
+                }
+            }
+        }
+
+        state.version = version;
+
+        inner.journal.insert(version, log);
+        while inner.journal.len() > inner.retained_versions {
+            inner.journal.pop_first();
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LeafStorage for MemoryLeafStorage {
+    async fn store_leaf(&self, leaf_id: &LeafId, leaf: Leaf) -> Result<()> {
+        self.pending().leaves.insert(leaf_id.clone(), leaf);
+
+        Ok(())
+    }
+
+    async fn get_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>> {
+        if let Some(leaf) = self.pending().leaves.get(leaf_id) {
+            return Ok(Some(leaf.clone()));
+        }
+
+        Ok(self.storage.read().state.leaves.get(leaf_id).cloned())
+    }
+
+    async fn get_leaf_by_index_key(&self, index_key: &IndexKey) -> Result<Vec<LeafWithId>> {
+        let pending = self.pending();
+        let inner = self.storage.read();
+        let state = &inner.state;
+
+        let mut leaves = BTreeMap::new();
+
+        if let Some(leaf_ids) = state.index.get(index_key) {
+            for leaf_id in leaf_ids {
+                if let Some(leaf) = state.leaves.get(leaf_id) {
+                    leaves.insert(leaf_id, leaf);
+                }
+            }
+        }
+
+        for (leaf_id, leaf) in &pending.leaves {
+            if &leaf.index == index_key {
+                leaves.insert(leaf_id, leaf);
+            }
+        }
+
+        let leaves = leaves
+            .into_iter()
+            .filter(|(leaf_id, _)| {
+                !state.spent.contains(*leaf_id) && !pending.spent.contains(*leaf_id)
+            })
+            .map(|(leaf_id, leaf)| LeafWithId {
+                leaf_id: leaf_id.clone(),
+                leaf: leaf.clone(),
+            })
+            .collect();
+
+        Ok(leaves)
+    }
+
+    async fn mark_leaf_as_spent(&self, leaf_id: &LeafId) -> Result<()> {
+        let mut pending = self.pending();
+
+        if !pending.leaves.contains_key(leaf_id)
+            && !self.storage.read().state.leaves.contains_key(leaf_id)
+        {
+            return Err(Error::SpentLeafNotFound(leaf_id.clone()));
+        }
+
+        pending.spent.insert(leaf_id.clone());
+
+        Ok(())
+    }
+
+    async fn is_spent(&self, leaf_id: &LeafId) -> Result<bool> {
+        if self.pending().spent.contains(leaf_id) {
+            return Ok(true);
+        }
+
+        Ok(self.storage.read().state.spent.contains(leaf_id))
+    }
+
+    async fn purge_spent_leaves(&self) -> Result<()> {
+        self.pending().purge_spent = true;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bbm_primitives::FixedBytes;
+
+    use super::*;
+    use crate::test_utils::{leaf, leaf_id};
+
+    #[tokio::test]
+    async fn test_commit_and_get() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 2,
+        });
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(1), leaf(7, 1))
+            .await
+            .unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(2), leaf(7, 2))
+            .await
+            .unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(3), leaf(8, 3))
+            .await
+            .unwrap();
+
+        // uncommitted writes are only visible through the same leaf storage
+        let other = storage.open_leaf_storage().unwrap();
+        assert_eq!(other.get_leaf(&leaf_id(1)).await.unwrap(), None);
+        assert_eq!(
+            leaf_storage.get_leaf(&leaf_id(1)).await.unwrap(),
+            Some(leaf(7, 1))
+        );
+
+        leaf_storage.commit(1).unwrap();
+        assert_eq!(storage.version(), 1);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert_eq!(
+            leaf_storage.get_leaf(&leaf_id(2)).await.unwrap(),
+            Some(leaf(7, 2))
+        );
+
+        let leaves = leaf_storage
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        assert_eq!(
+            leaves,
+            vec![
+                LeafWithId {
+                    leaf_id: leaf_id(1),
+                    leaf: leaf(7, 1)
+                },
+                LeafWithId {
+                    leaf_id: leaf_id(2),
+                    leaf: leaf(7, 2)
+                },
+            ]
+        );
+
+        // versions must increase
+        assert!(matches!(
+            leaf_storage.commit(1),
+            Err(Error::VersionNotIncreasing {
+                version: 1,
+                current: 1
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_spent_and_purge() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 2,
+        });
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(1), leaf(7, 1))
+            .await
+            .unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(2), leaf(7, 2))
+            .await
+            .unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+        assert!(leaf_storage.mark_leaf_as_spent(&leaf_id(9)).await.is_err());
+        assert!(leaf_storage.is_spent(&leaf_id(1)).await.unwrap());
+        leaf_storage.commit(2).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert!(leaf_storage.is_spent(&leaf_id(1)).await.unwrap());
+        assert!(!leaf_storage.is_spent(&leaf_id(2)).await.unwrap());
+        let leaves = leaf_storage
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].leaf_id, leaf_id(2));
+        assert!(leaf_storage.get_leaf(&leaf_id(1)).await.unwrap().is_some());
+
+        leaf_storage.purge_spent_leaves().await.unwrap();
+        leaf_storage.commit(3).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert_eq!(leaf_storage.get_leaf(&leaf_id(1)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_revert_to_version() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 2,
+        });
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(1), leaf(7, 1))
+            .await
+            .unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(2), leaf(7, 2))
+            .await
+            .unwrap();
+        leaf_storage.purge_spent_leaves().await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        storage.revert_to_version(1).await.unwrap();
+        assert_eq!(storage.version(), 1);
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        assert_eq!(
+            leaf_storage.get_leaf(&leaf_id(1)).await.unwrap(),
+            Some(leaf(7, 1))
+        );
+        assert_eq!(leaf_storage.get_leaf(&leaf_id(2)).await.unwrap(), None);
+
+        let leaves = leaf_storage
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        assert_eq!(
+            leaves,
+            vec![LeafWithId {
+                leaf_id: leaf_id(1),
+                leaf: leaf(7, 1)
+            }]
+        );
+
+        // version 2 was dropped by the revert
+        assert!(storage.revert_to_version(2).await.is_err());
+        leaf_storage.commit(2).unwrap();
+
+        // only the undo journals of the last two commits are kept
+        storage.open_leaf_storage().unwrap().commit(3).unwrap();
+        assert!(matches!(
+            storage.revert_to_version(0).await,
+            Err(Error::VersionNotRetained {
+                version: 1,
+                target: 0
+            })
+        ));
+        assert_eq!(storage.version(), 3);
+
+        storage.revert_to_version(1).await.unwrap();
+        assert_eq!(storage.version(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let storage = MemoryStorage::new(&StorageConfig {
+            retained_versions: 2,
+        });
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(1), leaf(7, 1))
+            .await
+            .unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(2), leaf(7, 2))
+            .await
+            .unwrap();
+        leaf_storage.commit(1).unwrap();
+
+        let pinned = storage.snapshot(1).unwrap();
+
+        let leaf_storage = storage.open_leaf_storage().unwrap();
+        leaf_storage.mark_leaf_as_spent(&leaf_id(1)).await.unwrap();
+        leaf_storage
+            .store_leaf(&leaf_id(3), leaf(7, 3))
+            .await
+            .unwrap();
+        leaf_storage.purge_spent_leaves().await.unwrap();
+        leaf_storage.commit(2).unwrap();
+
+        // taken before and after the commit, both see version 1
+        for snapshot in [pinned, storage.snapshot(1).unwrap()] {
+            assert_eq!(
+                snapshot.get_leaf(&leaf_id(1)).await.unwrap(),
+                Some(leaf(7, 1))
+            );
+            assert_eq!(snapshot.get_leaf(&leaf_id(3)).await.unwrap(), None);
+
+            let leaves = snapshot
+                .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+                .await
+                .unwrap();
+            let leaf_ids: Vec<_> = leaves.into_iter().map(|l| l.leaf_id).collect();
+            assert_eq!(leaf_ids, vec![leaf_id(1), leaf_id(2)]);
+        }
+
+        let snapshot = storage.snapshot(2).unwrap();
+        assert_eq!(snapshot.get_leaf(&leaf_id(1)).await.unwrap(), None);
+
+        let leaves = snapshot
+            .get_leaf_by_index_key(&FixedBytes([7u8; 32]))
+            .await
+            .unwrap();
+        let leaf_ids: Vec<_> = leaves.into_iter().map(|l| l.leaf_id).collect();
+        assert_eq!(leaf_ids, vec![leaf_id(2), leaf_id(3)]);
+
+        assert!(storage.snapshot(3).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+mod pending;
+pub(crate) use pending::*;
+
+mod journal;
+pub(crate) use journal::*;
+
+mod memory;
+pub use memory::*;
+
+mod disk;
+pub use disk::*;
@@ -0,0 +1,11 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bbm_primitives::{Leaf, LeafId};
+
+/// Changes buffered by a leaf storage until it is committed.
+#[derive(Default)]
+pub(crate) struct PendingChanges {
+    pub leaves: BTreeMap<LeafId, Leaf>,
+    pub spent: BTreeSet<LeafId>,
+    pub purge_spent: bool,
+}
@@ -0,0 +1,132 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bbm_primitives::{
+    Bytes, FixedBytes, Leaf, LeafId, OutPoint, Transaction, UnlockScript, UnlockScriptType,
+    UnsignedTransaction,
+};
+
+use crate::{WasmExecutorConfig, executors::WasmExecutor};
+
+/// Temporary directory removed when dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "bbm-core-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // wasmtime's cache worker may still be writing under the directory, retry until
+        // removal wins the race
+        for _ in 0..50 {
+            if std::fs::remove_dir_all(&self.0).is_ok() || !self.0.exists() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Executor with small limits, caching compiled modules under `home`.
+pub(crate) fn executor(home: &TempDir) -> WasmExecutor {
+    let home = home.path();
+    std::fs::write(home.join("wasmtime.toml"), "[cache]\n").unwrap();
+
+    let config = WasmExecutorConfig {
+        config_path: home.join("wasmtime.toml"),
+        cache_path: None,
+        module_cache_size: 2,
+        script_fuel_limit: 10_000,
+        transaction_fuel_limit: 15_000,
+        max_memory_pages: 2,
+        max_table_elements: 16,
+        max_instances: 1,
+        max_call_depth: 64,
+        max_wasm_stack: 512 * 1024,
+        script_workers: 4,
+    };
+
+    WasmExecutor::new(&config, home).unwrap()
+}
+
+pub(crate) fn leaf(index: u8, data: u8) -> Leaf {
+    Leaf {
+        version: 1,
+        nonce: 0,
+        owner: FixedBytes([1u8; 20]),
+        index: FixedBytes([index; 32]),
+        operator: Some(FixedBytes([9u8; 32])),
+        data: Bytes(vec![data; 3]),
+    }
+}
+
+pub(crate) fn leaf_id(id: u8) -> LeafId {
+    FixedBytes([id; 32])
+}
+
+/// Transaction spending `inputs` with empty unlockers, creating a single output.
+pub(crate) fn transaction(nonce: u64, inputs: Vec<LeafId>) -> Transaction {
+    let output = Leaf {
+        version: 1,
+        nonce: 0,
+        owner: FixedBytes([1u8; 20]),
+        index: FixedBytes([2u8; 32]),
+        operator: None,
+        data: Bytes(vec![]),
+    };
+
+    Transaction {
+        unlockers: vec![Bytes(vec![]); inputs.len()],
+        unsigned: UnsignedTransaction {
+            version: 1,
+            nonce,
+            inputs,
+            outputs: vec![output],
+        },
+    }
+}
+
+/// Leaf id of the first output of `transaction`.
+pub(crate) fn output(transaction: &Transaction) -> LeafId {
+    OutPoint {
+        txid: transaction.unsigned.hash().unwrap(),
+        index: 0,
+    }
+    .leaf_id()
+}
+
+/// Wasm unlock script running the code stored in `code_leaf`.
+pub(crate) fn unlock_script(code_leaf: &LeafId) -> UnlockScript {
+    UnlockScript {
+        version: 1,
+        ty: UnlockScriptType::Wasm,
+        code_leaf: code_leaf.0,
+        args: vec![],
+    }
+}
+
+/// Unlocker made of `script` alone, without witness data.
+pub(crate) fn unlocker(script: &UnlockScript) -> Bytes {
+    let mut v = Vec::new();
+    script.append_to_vec(&mut v).unwrap();
+    Bytes(v)
+}
@@ -1,7 +1,8 @@
-use anyhow::Result;
 use async_trait::async_trait;
 use bbm_primitives::{IndexKey, Leaf, LeafId, LeafWithId};
 
+use crate::Result;
+
 pub trait CommittableStorage {
     fn commit(self, version: u64) -> Result<()>;
 }
@@ -12,18 +13,40 @@ pub trait LeafStorage: CommittableStorage {
 
     async fn get_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>>;
 
+    /// Unspent leaves stored under `index_key`, ordered by leaf id.
     async fn get_leaf_by_index_key(&self, index_key: &IndexKey) -> Result<Vec<LeafWithId>>;
 
     async fn mark_leaf_as_spent(&self, leaf_id: &LeafId) -> Result<()>;
 
+    /// Whether the leaf has been marked as spent, by a prior commit or by this storage.
+    async fn is_spent(&self, leaf_id: &LeafId) -> Result<bool>;
+
+    /// Drop every spent leaf when this storage is committed.
     async fn purge_spent_leaves(&self) -> Result<()>;
 }
 
+/// Read-only view of the leaves committed at one version.
+///
+/// Later commits to the storage do not change what a snapshot returns.
+#[async_trait]
+pub trait LeafSnapshot: Send + Sync {
+    async fn get_leaf(&self, leaf_id: &LeafId) -> Result<Option<Leaf>>;
+
+    /// Unspent leaves stored under `index_key`, ordered by leaf id.
+    async fn get_leaf_by_index_key(&self, index_key: &IndexKey) -> Result<Vec<LeafWithId>>;
+}
+
 #[async_trait]
 pub trait Storage {
     type LeafStorage: LeafStorage;
 
+    type Snapshot: LeafSnapshot;
+
     async fn revert_to_version(&self, version: u64) -> Result<()>;
 
     fn open_leaf_storage(&self) -> Result<Self::LeafStorage>;
+
+    /// Pin a view to a committed `version`, either the current one or one that can still be
+    /// reverted to.
+    fn snapshot(&self, version: u64) -> Result<Self::Snapshot>;
 }
@@ -0,0 +1,11 @@
+#![no_main]
+
+use bbm_primitives::LeafParser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(parser) = LeafParser::new(data) {
+        let _ = parser.to_leaf();
+        let _ = parser.data();
+    }
+});
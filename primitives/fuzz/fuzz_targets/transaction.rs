@@ -0,0 +1,19 @@
+#![no_main]
+
+use bbm_primitives::{Transaction, TransactionParser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Transaction::from_slice(data);
+
+    // the strict decoder only accepts the canonical encoding
+    if let Ok(tx) = Transaction::from_slice_strict(data) {
+        assert_eq!(tx.to_vec().unwrap(), data);
+    }
+
+    if let Ok(parser) = TransactionParser::new(data) {
+        for i in 0..parser.unlockers_count() {
+            let _ = parser.unlocker(i);
+        }
+    }
+});
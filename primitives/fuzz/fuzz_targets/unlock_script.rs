@@ -0,0 +1,10 @@
+#![no_main]
+
+use bbm_primitives::UnlockScript;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(script) = UnlockScript::from_slice(data) {
+        let _ = script.address();
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use bbm_primitives::{UnsignedTransaction, UnsignedTransactionParser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = UnsignedTransaction::from_slice(data);
+
+    // the strict decoder only accepts the canonical encoding
+    if let Ok(tx) = UnsignedTransaction::from_slice_strict(data) {
+        assert_eq!(tx.to_vec().unwrap(), data);
+    }
+
+    if let Ok(parser) = UnsignedTransactionParser::new(data) {
+        for i in 0..parser.inputs_count() {
+            let _ = parser.input(i);
+        }
+
+        for i in 0..parser.outputs_count() {
+            let _ = parser.output(i).map(|output| output.data().len());
+        }
+
+        let _ = parser.to_unsigned_transaction();
+    }
+});
@@ -13,6 +13,18 @@ pub enum Error {
     // InputUnlockerLengthMismatch(usize, usize),
     #[error("wrong length {0} for leaf, expected {1}")]
     WrongLengthForLeaf(usize, usize),
+
+    #[error("wrong length {0} for outpoint, expected {1}")]
+    WrongLengthForOutPoint(usize, usize),
+
+    #[error("unknown unlock script type {0}")]
+    UnknownUnlockScriptType(u8),
+
+    #[error("{0} trailing bytes after the encoded value")]
+    TrailingBytes(usize),
+
+    #[error("length {1} in the output length table for output {0}, but its data has length {2}")]
+    OutputLengthMismatch(usize, usize, usize),
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -30,6 +30,12 @@ impl Leaf {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeafWithId {
+    pub leaf_id: LeafId,
+    pub leaf: Leaf,
+}
+
 pub struct LeafParser<T> {
     inner: T,
 }
@@ -43,6 +49,11 @@ where
 
         Ok(Self { inner })
     }
+
+    /// Wrap bytes already known to hold a valid leaf.
+    pub(crate) fn new_unchecked(inner: T) -> Self {
+        Self { inner }
+    }
 }
 
 const LEAF_HEADER_LENGTH: usize = 1 + 8 + 20 + 32 + 32 + 4;
@@ -51,22 +62,20 @@ fn check_leaf_length(slice: &[u8]) -> Result<()> {
     let len = slice.len();
 
     if len < LEAF_HEADER_LENGTH {
-        return Err(Error::WrongLengthForLeaf(len, 97));
+        return Err(Error::WrongLengthForLeaf(len, LEAF_HEADER_LENGTH));
     }
 
     let data_len = u32::from_be_bytes(slice[1..5].try_into().unwrap()) as usize;
+    let leaf_len = LEAF_HEADER_LENGTH.saturating_add(data_len);
 
-    if len < LEAF_HEADER_LENGTH + data_len {
-        return Err(Error::WrongLengthForLeaf(
-            len,
-            LEAF_HEADER_LENGTH + data_len,
-        ));
+    if len < leaf_len {
+        return Err(Error::WrongLengthForLeaf(len, leaf_len));
     }
 
     Ok(())
 }
 
-impl<'a> LeafParser<&'a [u8]> {
+impl LeafParser<&[u8]> {
     pub fn version(&self) -> u8 {
         self.inner[0]
     }
@@ -119,7 +128,7 @@ impl<'a> LeafParser<&'a [u8]> {
     }
 }
 
-impl<'a> LeafParser<&'a mut [u8]> {
+impl LeafParser<&mut [u8]> {
     pub fn version(&self) -> u8 {
         self.inner[0]
     }
@@ -4,14 +4,17 @@ pub use types::*;
 mod leaf;
 pub use leaf::*;
 
-// mod script;
-// pub use script::*;
+mod outpoint;
+pub use outpoint::*;
 
-// mod unsigned_tx;
-// pub use unsigned_tx::*;
+mod script;
+pub use script::*;
 
-// mod tx;
-// pub use tx::*;
+mod unsigned_tx;
+pub use unsigned_tx::*;
+
+mod tx;
+pub use tx::*;
 
 mod error;
 pub use error::*;
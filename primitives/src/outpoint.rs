@@ -0,0 +1,88 @@
+use sha3::{Digest, Sha3_256};
+
+use crate::{Error, FixedBytes, LeafId, Result, Txid};
+
+/// Output `index` of the transaction `txid`.
+///
+/// The leaf created by this output is named by `leaf_id`, the outpoint itself is kept for
+/// wallets and tools that need to know where a leaf comes from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OutPoint {
+    pub txid: Txid,
+    pub index: u32,
+}
+
+impl OutPoint {
+    /// Encoded length: txid followed by the big-endian index.
+    pub const LENGTH: usize = 32 + 4;
+
+    pub fn append_to_vec(&self, v: &mut Vec<u8>) {
+        v.extend_from_slice(&self.txid.0);
+        v.extend_from_slice(&self.index.to_be_bytes());
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(Self::LENGTH);
+        self.append_to_vec(&mut v);
+        v
+    }
+
+    pub fn from_slice(slice: &[u8]) -> Result<Self> {
+        if slice.len() != Self::LENGTH {
+            return Err(Error::WrongLengthForOutPoint(slice.len(), Self::LENGTH));
+        }
+
+        let txid = Txid::from_slice(&slice[..32])?;
+        let index = u32::from_be_bytes(slice[32..].try_into().unwrap());
+
+        Ok(Self { txid, index })
+    }
+
+    /// Leaf id of the output, `sha3_256(txid || index)` over the encoded outpoint.
+    pub fn leaf_id(&self) -> LeafId {
+        let mut hasher = Sha3_256::new();
+        hasher.update(self.txid.0);
+        hasher.update(self.index.to_be_bytes());
+
+        FixedBytes(hasher.finalize().into())
+    }
+}
+
+impl From<&OutPoint> for LeafId {
+    fn from(outpoint: &OutPoint) -> Self {
+        outpoint.leaf_id()
+    }
+}
+
+impl From<OutPoint> for LeafId {
+    fn from(outpoint: OutPoint) -> Self {
+        outpoint.leaf_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outpoint() {
+        let outpoint = OutPoint {
+            txid: FixedBytes([1u8; 32]),
+            index: 2,
+        };
+
+        let bytes = outpoint.to_vec();
+        assert_eq!(bytes.len(), OutPoint::LENGTH);
+        assert_eq!(OutPoint::from_slice(&bytes).unwrap(), outpoint);
+        assert!(OutPoint::from_slice(&bytes[1..]).is_err());
+
+        let leaf_id = LeafId::from(&outpoint);
+        assert_eq!(leaf_id.0[..], Sha3_256::digest(&bytes)[..]);
+
+        let next = OutPoint {
+            index: 3,
+            ..outpoint.clone()
+        };
+        assert_ne!(next.leaf_id(), leaf_id);
+    }
+}
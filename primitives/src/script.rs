@@ -47,19 +47,18 @@ impl UnlockScript {
             return Err(Error::WrongLengthForUnlockScript(slice.len(), 38));
         }
 
-        let args_len = u32::from_be_bytes(slice[1..5].try_into().unwrap());
+        let args_len = u32::from_be_bytes(slice[1..5].try_into().unwrap()) as usize;
+        let script_len = args_len.saturating_add(38);
 
-        if slice.len() < 38 + args_len as usize {
-            return Err(Error::WrongLengthForUnlockScript(
-                slice.len(),
-                38 + args_len as usize,
-            ));
+        if slice.len() < script_len {
+            return Err(Error::WrongLengthForUnlockScript(slice.len(), script_len));
         }
 
         let version = u8::from_be_bytes(slice[0..1].try_into().unwrap());
-        let ty = UnlockScriptType::from_u8(slice[5]).unwrap();
+        let ty =
+            UnlockScriptType::from_u8(slice[5]).ok_or(Error::UnknownUnlockScriptType(slice[5]))?;
         let code_leaf = slice[6..38].try_into().unwrap();
-        let args = slice[38..38 + args_len as usize].to_vec();
+        let args = slice[38..script_len].to_vec();
 
         Ok(Self {
             version,
@@ -69,13 +68,49 @@ impl UnlockScript {
         })
     }
 
+    /// Length of the encoded script, the rest of an unlocker is witness data.
+    pub fn encoded_len(&self) -> usize {
+        38 + self.args.len()
+    }
+
     pub fn address(&self) -> Result<Address> {
         let mut hasher = Sha3_256::new();
-        hasher.update(&[self.version, self.ty.to_u8()]);
-        hasher.update(&self.code_leaf);
+        hasher.update([self.version, self.ty.to_u8()]);
+        hasher.update(self.code_leaf);
         hasher.update(&self.args);
 
         let hash = hasher.finalize();
         Address::from_slice(&hash[..20])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlock_script_malformed() {
+        let script = UnlockScript {
+            version: 1,
+            ty: UnlockScriptType::Wasm,
+            code_leaf: [1u8; 32],
+            args: vec![2, 3],
+        };
+
+        let mut bytes = Vec::new();
+        script.append_to_vec(&mut bytes).unwrap();
+
+        let decoded = UnlockScript::from_slice(&bytes).unwrap();
+        assert_eq!(decoded.encoded_len(), bytes.len());
+
+        for len in 0..bytes.len() {
+            assert!(UnlockScript::from_slice(&bytes[..len]).is_err());
+        }
+
+        bytes[5] = 9;
+        assert!(matches!(
+            UnlockScript::from_slice(&bytes),
+            Err(Error::UnknownUnlockScriptType(9))
+        ));
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{Bytes, Result, UnsignedTransaction};
+use std::ops::Range;
+
+use crate::{Bytes, Error, Result, UnsignedTransaction, UnsignedTransactionParser};
 
 #[derive(Debug, PartialEq)]
 pub struct Transaction {
@@ -34,44 +36,142 @@ impl Transaction {
 
     pub fn from_slice(slice: &[u8]) -> Result<Self> {
         let unsigned = UnsignedTransaction::from_slice(slice)?;
-        let unlocker_count =
-            u32::from_be_bytes(slice[slice.len() - 4..slice.len()].try_into().unwrap()) as usize;
-
-        let mut unlockers = Vec::new();
+        let (unlockers, _) = unlockers_from_slice(slice)?;
 
-        let mut unlockers_length_pos = slice.len() - 4;
-        let mut unlockers_begin_pos = slice.len() - 4 - unlocker_count * 4;
-
-        for _ in 0..unlocker_count {
-            let begin = unlockers_length_pos;
-            let end = begin - 4;
-            let unlocker_len = u32::from_be_bytes(slice[end..begin].try_into().unwrap()) as usize;
-            unlockers_length_pos = end;
+        Ok(Self {
+            unsigned,
+            unlockers,
+        })
+    }
 
-            let begin = unlockers_begin_pos;
-            let end = begin - unlocker_len;
+    /// Decode the canonical encoding only, so that `to_vec` gives back `slice`.
+    ///
+    /// The unsigned transaction must end exactly where the first unlocker begins and be
+    /// canonical itself.
+    pub fn from_slice_strict(slice: &[u8]) -> Result<Self> {
+        TransactionParser::new(slice)?.to_transaction()
+    }
+}
 
-            let data = &slice[end..begin];
-            let unlocker = Bytes::from_slice(data);
+/// Zero-copy view of an encoded `Transaction`.
+///
+/// `new` validates the encoding once, by the rules of `Transaction::from_slice_strict`.
+/// Fields are then read in place.
+pub struct TransactionParser<T> {
+    unsigned: UnsignedTransactionParser<T>,
+    unlockers: Vec<Range<usize>>,
+}
 
-            unlockers.push(unlocker);
+impl<T> TransactionParser<T>
+where
+    T: AsRef<[u8]>,
+{
+    pub fn new(inner: T) -> Result<Self> {
+        let (unlockers, unsigned_len) = unlocker_ranges(inner.as_ref())?;
+        let unsigned = UnsignedTransactionParser::new(inner)?;
 
-            unlockers_begin_pos = end;
+        if unsigned.tx_len() > unsigned_len {
+            return Err(Error::WrongLengthForTx(unsigned_len, unsigned.tx_len()));
         }
 
-        // unlockers.res
+        if unsigned.tx_len() < unsigned_len {
+            return Err(Error::TrailingBytes(unsigned_len - unsigned.tx_len()));
+        }
 
         Ok(Self {
             unsigned,
             unlockers,
         })
     }
+
+    pub fn unsigned(&self) -> &UnsignedTransactionParser<T> {
+        &self.unsigned
+    }
+
+    pub fn unlockers_count(&self) -> usize {
+        self.unlockers.len()
+    }
+
+    pub fn unlocker(&self, i: usize) -> Option<&[u8]> {
+        let range = self.unlockers.get(i)?.clone();
+
+        Some(&self.unsigned.as_slice()[range])
+    }
+
+    pub fn to_transaction(&self) -> Result<Transaction> {
+        let unlockers = self
+            .unlockers
+            .iter()
+            .map(|range| Bytes::from_slice(&self.unsigned.as_slice()[range.clone()]));
+
+        Ok(Transaction {
+            unsigned: self.unsigned.to_unsigned_transaction()?,
+            unlockers: unlockers.collect(),
+        })
+    }
+}
+
+/// Decode the unlockers stored backwards from the end of `slice`.
+///
+/// Also returns the position of the first unlocker byte, where the unsigned transaction ends.
+fn unlockers_from_slice(slice: &[u8]) -> Result<(Vec<Bytes>, usize)> {
+    let (ranges, unsigned_len) = unlocker_ranges(slice)?;
+
+    let unlockers = ranges
+        .into_iter()
+        .map(|range| Bytes::from_slice(&slice[range]))
+        .collect();
+
+    Ok((unlockers, unsigned_len))
+}
+
+/// Position of each unlocker in `slice`, and of the first unlocker byte.
+fn unlocker_ranges(slice: &[u8]) -> Result<(Vec<Range<usize>>, usize)> {
+    let Some((rest, count)) = slice.split_last_chunk::<4>() else {
+        return Err(Error::WrongLengthForTx(slice.len(), 4));
+    };
+    let unlocker_count = u32::from_be_bytes(*count) as usize;
+
+    // unlockers and their lengths are stored backwards from the end
+    let table_len = unlocker_count.saturating_mul(4);
+    if rest.len() < table_len {
+        return Err(Error::WrongLengthForTx(
+            slice.len(),
+            table_len.saturating_add(4),
+        ));
+    }
+
+    let mut unlockers = Vec::with_capacity(unlocker_count);
+
+    let mut unlockers_length_pos = rest.len();
+    let mut unlockers_begin_pos = rest.len() - table_len;
+
+    for _ in 0..unlocker_count {
+        let begin = unlockers_length_pos;
+        let end = begin - 4;
+        let unlocker_len = u32::from_be_bytes(slice[end..begin].try_into().unwrap()) as usize;
+        unlockers_length_pos = end;
+
+        let begin = unlockers_begin_pos;
+        let Some(end) = begin.checked_sub(unlocker_len) else {
+            return Err(Error::WrongLengthForTx(
+                slice.len(),
+                (slice.len() - begin).saturating_add(unlocker_len),
+            ));
+        };
+
+        unlockers.push(end..begin);
+
+        unlockers_begin_pos = end;
+    }
+
+    Ok((unlockers, unlockers_begin_pos))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{FixedBytes, Leaf, LeafId};
+    use crate::{FixedBytes, Leaf, OutPoint};
 
     #[test]
     fn test_transaction_serialization_deserialization() {
@@ -81,23 +181,29 @@ mod tests {
                 version: 1,
                 nonce: 12345,
                 inputs: vec![
-                    LeafId {
+                    OutPoint {
                         txid: FixedBytes([1u8; 32]),
                         index: 0,
-                    },
-                    LeafId {
+                    }
+                    .leaf_id(),
+                    OutPoint {
                         txid: FixedBytes([2u8; 32]),
                         index: 1,
-                    },
+                    }
+                    .leaf_id(),
                 ],
                 outputs: vec![Leaf {
                     version: 1,
+                    nonce: 1,
                     owner: FixedBytes([3u8; 20]),
-                    index_key: FixedBytes([4u8; 32]),
-                    operator: Some(LeafId {
-                        txid: FixedBytes([5u8; 32]),
-                        index: 2,
-                    }),
+                    index: FixedBytes([4u8; 32]),
+                    operator: Some(
+                        OutPoint {
+                            txid: FixedBytes([5u8; 32]),
+                            index: 2,
+                        }
+                        .leaf_id(),
+                    ),
                     data: Bytes(vec![60, 70, 80, 90]),
                 }],
             },
@@ -146,14 +252,18 @@ mod tests {
             unsigned: UnsignedTransaction {
                 version: 1,
                 nonce: 5000,
-                inputs: vec![LeafId {
-                    txid: FixedBytes([7u8; 32]),
-                    index: 3,
-                }],
+                inputs: vec![
+                    OutPoint {
+                        txid: FixedBytes([7u8; 32]),
+                        index: 3,
+                    }
+                    .leaf_id(),
+                ],
                 outputs: vec![Leaf {
                     version: 1,
+                    nonce: 1,
                     owner: FixedBytes([8u8; 20]),
-                    index_key: FixedBytes([9u8; 32]),
+                    index: FixedBytes([9u8; 32]),
                     operator: None,
                     data: Bytes(vec![100, 101, 102, 103]),
                 }],
@@ -178,34 +288,42 @@ mod tests {
                 version: 3,
                 nonce: 99999,
                 inputs: vec![
-                    LeafId {
+                    OutPoint {
                         txid: FixedBytes([11u8; 32]),
                         index: 5,
-                    },
-                    LeafId {
+                    }
+                    .leaf_id(),
+                    OutPoint {
                         txid: FixedBytes([12u8; 32]),
                         index: 6,
-                    },
-                    LeafId {
+                    }
+                    .leaf_id(),
+                    OutPoint {
                         txid: FixedBytes([13u8; 32]),
                         index: 7,
-                    },
+                    }
+                    .leaf_id(),
                 ],
                 outputs: vec![
                     Leaf {
                         version: 1,
+                        nonce: 1,
                         owner: FixedBytes([14u8; 20]),
-                        index_key: FixedBytes([15u8; 32]),
-                        operator: Some(LeafId {
-                            txid: FixedBytes([16u8; 32]),
-                            index: 8,
-                        }),
+                        index: FixedBytes([15u8; 32]),
+                        operator: Some(
+                            OutPoint {
+                                txid: FixedBytes([16u8; 32]),
+                                index: 8,
+                            }
+                            .leaf_id(),
+                        ),
                         data: Bytes(vec![1, 2, 3]),
                     },
                     Leaf {
                         version: 2,
+                        nonce: 2,
                         owner: FixedBytes([17u8; 20]),
-                        index_key: FixedBytes([18u8; 32]),
+                        index: FixedBytes([18u8; 32]),
                         operator: None,
                         data: Bytes(vec![4, 5]),
                     },
@@ -235,18 +353,25 @@ mod tests {
             unsigned: UnsignedTransaction {
                 version: 1,
                 nonce: 777,
-                inputs: vec![LeafId {
-                    txid: FixedBytes([20u8; 32]),
-                    index: 10,
-                }],
+                inputs: vec![
+                    OutPoint {
+                        txid: FixedBytes([20u8; 32]),
+                        index: 10,
+                    }
+                    .leaf_id(),
+                ],
                 outputs: vec![Leaf {
                     version: 1,
+                    nonce: 1,
                     owner: FixedBytes([21u8; 20]),
-                    index_key: FixedBytes([22u8; 32]),
-                    operator: Some(LeafId {
-                        txid: FixedBytes([23u8; 32]),
-                        index: 11,
-                    }),
+                    index: FixedBytes([22u8; 32]),
+                    operator: Some(
+                        OutPoint {
+                            txid: FixedBytes([23u8; 32]),
+                            index: 11,
+                        }
+                        .leaf_id(),
+                    ),
                     data: Bytes(vec![1, 2, 3, 4, 5]),
                 }],
             },
@@ -274,14 +399,18 @@ mod tests {
             unsigned: UnsignedTransaction {
                 version: 1,
                 nonce: 111,
-                inputs: vec![LeafId {
-                    txid: FixedBytes([25u8; 32]),
-                    index: 0,
-                }],
+                inputs: vec![
+                    OutPoint {
+                        txid: FixedBytes([25u8; 32]),
+                        index: 0,
+                    }
+                    .leaf_id(),
+                ],
                 outputs: vec![Leaf {
                     version: 1,
+                    nonce: 1,
                     owner: FixedBytes([26u8; 20]),
-                    index_key: FixedBytes([27u8; 32]),
+                    index: FixedBytes([27u8; 32]),
                     operator: None,
                     data: Bytes(vec![99]),
                 }],
@@ -337,18 +466,25 @@ mod tests {
             unsigned: UnsignedTransaction {
                 version: 5,
                 nonce: 54321,
-                inputs: vec![LeafId {
-                    txid: FixedBytes([30u8; 32]),
-                    index: 15,
-                }],
+                inputs: vec![
+                    OutPoint {
+                        txid: FixedBytes([30u8; 32]),
+                        index: 15,
+                    }
+                    .leaf_id(),
+                ],
                 outputs: vec![Leaf {
                     version: 3,
+                    nonce: 3,
                     owner: FixedBytes([31u8; 20]),
-                    index_key: FixedBytes([32u8; 32]),
-                    operator: Some(LeafId {
-                        txid: FixedBytes([33u8; 32]),
-                        index: 16,
-                    }),
+                    index: FixedBytes([32u8; 32]),
+                    operator: Some(
+                        OutPoint {
+                            txid: FixedBytes([33u8; 32]),
+                            index: 16,
+                        }
+                        .leaf_id(),
+                    ),
                     data: Bytes(vec![77, 88, 99]),
                 }],
             },
@@ -371,4 +507,112 @@ mod tests {
             "Serialized bytes should be identical"
         );
     }
+
+    #[test]
+    fn test_transaction_malformed() {
+        let tx = Transaction {
+            unsigned: UnsignedTransaction {
+                version: 1,
+                nonce: 1,
+                inputs: vec![FixedBytes([1u8; 32])],
+                outputs: vec![Leaf {
+                    version: 1,
+                    nonce: 1,
+                    owner: FixedBytes([2u8; 20]),
+                    index: FixedBytes([3u8; 32]),
+                    operator: None,
+                    data: Bytes(vec![4, 5]),
+                }],
+            },
+            unlockers: vec![Bytes(vec![6, 7, 8])],
+        };
+        let serialized = tx.to_vec().unwrap();
+
+        // truncated or corrupted input is an error, never a panic
+        for len in 0..serialized.len() {
+            let _ = Transaction::from_slice(&serialized[..len]);
+        }
+
+        for i in 0..serialized.len() {
+            let mut corrupted = serialized.clone();
+            corrupted[i] = 0xff;
+            let _ = Transaction::from_slice(&corrupted);
+        }
+
+        let mut huge_count = serialized.clone();
+        let len = huge_count.len();
+        huge_count[len - 4..].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(Transaction::from_slice(&huge_count).is_err());
+    }
+
+    #[test]
+    fn test_transaction_strict() {
+        let tx = Transaction {
+            unsigned: UnsignedTransaction {
+                version: 1,
+                nonce: 1,
+                inputs: vec![FixedBytes([1u8; 32])],
+                outputs: vec![],
+            },
+            unlockers: vec![Bytes(vec![2, 3])],
+        };
+        let serialized = tx.to_vec().unwrap();
+
+        let decoded = Transaction::from_slice_strict(&serialized).unwrap();
+        assert_eq!(decoded.to_vec().unwrap(), serialized);
+
+        // a gap between the unsigned transaction and the unlockers
+        let unsigned_len = tx.unsigned.to_vec().unwrap().len();
+        let mut gap = serialized.clone();
+        gap.insert(unsigned_len, 0);
+        assert_eq!(Transaction::from_slice(&gap).unwrap(), tx);
+        assert!(matches!(
+            Transaction::from_slice_strict(&gap),
+            Err(Error::TrailingBytes(1))
+        ));
+
+        // unlockers overlapping the unsigned transaction
+        let mut overlap = serialized.clone();
+        let len = overlap.len();
+        overlap[len - 8..len - 4].copy_from_slice(&3u32.to_be_bytes());
+        assert!(Transaction::from_slice_strict(&overlap).is_err());
+    }
+
+    #[test]
+    fn test_transaction_parser() {
+        let tx = Transaction {
+            unsigned: UnsignedTransaction {
+                version: 1,
+                nonce: 1,
+                inputs: vec![FixedBytes([1u8; 32]), FixedBytes([2u8; 32])],
+                outputs: vec![Leaf {
+                    version: 1,
+                    nonce: 1,
+                    owner: FixedBytes([3u8; 20]),
+                    index: FixedBytes([4u8; 32]),
+                    operator: None,
+                    data: Bytes(vec![5]),
+                }],
+            },
+            unlockers: vec![Bytes(vec![6, 7]), Bytes(vec![]), Bytes(vec![8])],
+        };
+        let serialized = tx.to_vec().unwrap();
+
+        let parser = TransactionParser::new(serialized.as_slice()).unwrap();
+        assert_eq!(parser.unsigned().inputs_count(), 2);
+        assert_eq!(parser.unsigned().input(0), Some(&[1u8; 32]));
+        assert_eq!(parser.unsigned().output(0).unwrap().data(), &[5]);
+
+        assert_eq!(parser.unlockers_count(), 3);
+        assert_eq!(parser.unlocker(0), Some(&[6u8, 7][..]));
+        assert_eq!(parser.unlocker(1), Some(&[][..]));
+        assert_eq!(parser.unlocker(2), Some(&[8u8][..]));
+        assert_eq!(parser.unlocker(3), None);
+
+        assert_eq!(parser.to_transaction().unwrap(), tx);
+
+        for len in 0..serialized.len() {
+            assert!(TransactionParser::new(&serialized[..len]).is_err());
+        }
+    }
 }
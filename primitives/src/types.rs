@@ -10,7 +10,7 @@ pub type IndexKey = FixedBytes<32>;
 
 pub type Address = FixedBytes<20>;
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct FixedBytes<const N: usize>(pub [u8; N]);
 
 impl<const N: usize> FixedBytes<N> {
@@ -1,6 +1,6 @@
 use sha3::Digest;
 
-use crate::{Address, Bytes, Error, IndexKey, Leaf, LeafId, Result, Txid};
+use crate::{Bytes, Error, FixedBytes, Leaf, LeafId, LeafParser, Result, Txid};
 
 #[derive(Debug, PartialEq)]
 pub struct UnsignedTransaction {
@@ -43,8 +43,6 @@ impl UnsignedTransaction {
     }
 
     pub fn from_slice(slice: &[u8]) -> Result<Self> {
-        const HEADER_LENGTH: usize = 1 + 9 + 4 + 4 - 1;
-
         if slice.len() < HEADER_LENGTH {
             return Err(Error::WrongLengthForTx(slice.len(), HEADER_LENGTH));
         }
@@ -55,74 +53,40 @@ impl UnsignedTransaction {
         let inputs_count = u32::from_be_bytes(slice[9..13].try_into().unwrap()) as usize;
         let outputs_count = u32::from_be_bytes(slice[13..17].try_into().unwrap()) as usize;
 
-        let inputs_length_pos = HEADER_LENGTH;
-        let mut inputs_begin_pos = inputs_length_pos + outputs_count * 4;
+        // output length table, then 32 bytes for each input
+        let mut inputs_begin_pos = outputs_count
+            .saturating_mul(4)
+            .saturating_add(HEADER_LENGTH);
+        let inputs_end_pos = inputs_count
+            .saturating_mul(32)
+            .saturating_add(inputs_begin_pos);
+
+        if slice.len() < inputs_end_pos {
+            return Err(Error::WrongLengthForTx(slice.len(), inputs_end_pos));
+        }
 
         let mut inputs = Vec::new();
 
         for _ in 0..inputs_count {
-            // Get txid for input
+            // Get leaf id for input
             let begin = inputs_begin_pos;
             let end = begin + 32;
-            let txid = LeafId::from_slice(&slice[begin..end])?;
+            let leaf_id = LeafId::from_slice(&slice[begin..end])?;
 
-            inputs.push(txid);
+            inputs.push(leaf_id);
 
             inputs_begin_pos = end;
         }
 
+        // the length table is implied by the data length in each leaf
         let mut outputs = Vec::new();
-
-        let outputs_length_pos = inputs_length_pos;
         let mut outputs_begin_pos = inputs_begin_pos;
 
-        for i in 0..outputs_count {
-            // Get output length for output
-            let begin = outputs_length_pos + i * 4;
-            let end = begin + 4;
-            let bytes = slice[begin..end].try_into().unwrap();
-            let output_len = u32::from_be_bytes(bytes) as usize;
-
-            // Get output version
-            let begin = outputs_begin_pos;
-            let end = begin + 1;
-            let version = slice[begin];
-
-            // Get output owner
-            let begin = end;
-            let end = begin + 20;
-            let owner = Address::from_slice(&slice[begin..end])?;
-
-            // Get output index key
-            let begin = end;
-            let end = begin + 32;
-            let index = IndexKey::from_slice(&slice[begin..end])?;
+        for _ in 0..outputs_count {
+            let parser = LeafParser::new(&slice[outputs_begin_pos..])?;
+            outputs.push(parser.to_leaf()?);
 
-            // Get output operator txid
-            let begin = end;
-            let end = begin + 32;
-            let operator = LeafId::from_slice(&slice[begin..end])?;
-
-            let operator = if operator.is_none() {
-                None
-            } else {
-                Some(operator)
-            };
-
-            // Get output data
-            let begin = end;
-            let end = begin + output_len;
-            let data = Bytes::from_slice(&slice[begin..end]);
-
-            outputs.push(Leaf {
-                version,
-                owner,
-                index,
-                operator,
-                data,
-            });
-
-            outputs_begin_pos = end;
+            outputs_begin_pos += parser.leaf_len();
         }
 
         Ok(Self {
@@ -133,6 +97,20 @@ impl UnsignedTransaction {
         })
     }
 
+    /// Decode the canonical encoding only, so that `to_vec` gives back `slice`.
+    ///
+    /// The output length table must match the data length of each output and no bytes may
+    /// follow the last output.
+    pub fn from_slice_strict(slice: &[u8]) -> Result<Self> {
+        let parser = UnsignedTransactionParser::new(slice)?;
+
+        if parser.tx_len() != slice.len() {
+            return Err(Error::TrailingBytes(slice.len() - parser.tx_len()));
+        }
+
+        parser.to_unsigned_transaction()
+    }
+
     pub fn hash(&self) -> Result<Txid> {
         let mut hasher = sha3::Sha3_256::new();
 
@@ -141,20 +119,144 @@ impl UnsignedTransaction {
 
         let hash = hasher.finalize();
 
-        Ok(Txid::from_slice(&hash)?)
+        Txid::from_slice(&hash)
+    }
+}
+
+const HEADER_LENGTH: usize = 1 + 8 + 4 + 4;
+
+/// Zero-copy view of an encoded `UnsignedTransaction`.
+///
+/// `new` validates the encoding once, by the rules of `from_slice_strict` except that bytes
+/// may follow the transaction. Fields are then read in place.
+pub struct UnsignedTransactionParser<T> {
+    inner: T,
+    // start of each output, then the end of the transaction
+    outputs: Vec<usize>,
+}
+
+impl<T> UnsignedTransactionParser<T>
+where
+    T: AsRef<[u8]>,
+{
+    pub fn new(inner: T) -> Result<Self> {
+        let slice = inner.as_ref();
+
+        if slice.len() < HEADER_LENGTH {
+            return Err(Error::WrongLengthForTx(slice.len(), HEADER_LENGTH));
+        }
+
+        let inputs_count = u32::from_be_bytes(slice[9..13].try_into().unwrap()) as usize;
+        let outputs_count = u32::from_be_bytes(slice[13..17].try_into().unwrap()) as usize;
+
+        let inputs_end_pos = inputs_count
+            .saturating_mul(32)
+            .saturating_add(outputs_count.saturating_mul(4))
+            .saturating_add(HEADER_LENGTH);
+
+        if slice.len() < inputs_end_pos {
+            return Err(Error::WrongLengthForTx(slice.len(), inputs_end_pos));
+        }
+
+        let mut outputs = Vec::with_capacity(outputs_count + 1);
+        let mut pos = inputs_end_pos;
+
+        for i in 0..outputs_count {
+            let parser = LeafParser::new(&slice[pos..])?;
+
+            let begin = HEADER_LENGTH + i * 4;
+            let table_len = u32::from_be_bytes(slice[begin..begin + 4].try_into().unwrap());
+            if table_len != parser.data_len() {
+                return Err(Error::OutputLengthMismatch(
+                    i,
+                    table_len as usize,
+                    parser.data_len() as usize,
+                ));
+            }
+
+            outputs.push(pos);
+            pos += parser.leaf_len();
+        }
+
+        outputs.push(pos);
+
+        Ok(Self { inner, outputs })
+    }
+
+    /// Length of the encoded transaction, bytes after it are ignored.
+    pub fn tx_len(&self) -> usize {
+        self.outputs[self.outputs.len() - 1]
+    }
+
+    pub fn version(&self) -> u8 {
+        self.inner.as_ref()[0]
+    }
+
+    pub fn nonce(&self) -> u64 {
+        u64::from_be_bytes(self.inner.as_ref()[1..9].try_into().unwrap())
+    }
+
+    pub fn inputs_count(&self) -> usize {
+        u32::from_be_bytes(self.inner.as_ref()[9..13].try_into().unwrap()) as usize
+    }
+
+    pub fn outputs_count(&self) -> usize {
+        self.outputs.len() - 1
+    }
+
+    /// Leaf id spent by input `i`.
+    pub fn input(&self, i: usize) -> Option<&[u8; 32]> {
+        if i >= self.inputs_count() {
+            return None;
+        }
+
+        let begin = HEADER_LENGTH + self.outputs_count() * 4 + i * 32;
+        self.inner.as_ref()[begin..begin + 32].try_into().ok()
+    }
+
+    pub fn output(&self, i: usize) -> Option<LeafParser<&[u8]>> {
+        let begin = *self.outputs.get(i)?;
+        let end = *self.outputs.get(i + 1)?;
+
+        Some(LeafParser::new_unchecked(&self.inner.as_ref()[begin..end]))
+    }
+
+    pub fn to_unsigned_transaction(&self) -> Result<UnsignedTransaction> {
+        let mut inputs = Vec::with_capacity(self.inputs_count());
+        for i in 0..self.inputs_count() {
+            inputs.push(FixedBytes(*self.input(i).unwrap()));
+        }
+
+        let mut outputs = Vec::with_capacity(self.outputs_count());
+        for i in 0..self.outputs_count() {
+            outputs.push(self.output(i).unwrap().to_leaf()?);
+        }
+
+        Ok(UnsignedTransaction {
+            version: self.version(),
+            nonce: self.nonce(),
+            inputs,
+            outputs,
+        })
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        self.inner.as_ref()
     }
 }
 
 pub struct FilledTransaction {
+    pub txid: Txid,
+    pub unsigned: UnsignedTransaction,
+    /// Spent leaves, one for each of `unsigned.inputs` in the same order.
     pub inputs: Vec<Leaf>,
     pub unlockers: Vec<Bytes>,
-    pub outputs: Vec<Leaf>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::FixedBytes;
+    use crate::OutPoint;
 
     #[test]
     fn test_transaction_serialization_deserialization() {
@@ -163,23 +265,29 @@ mod tests {
             version: 1,
             nonce: 12345,
             inputs: vec![
-                LeafId {
+                OutPoint {
                     txid: FixedBytes([1u8; 32]),
                     index: 0,
-                },
-                LeafId {
+                }
+                .leaf_id(),
+                OutPoint {
                     txid: FixedBytes([2u8; 32]),
                     index: 1,
-                },
+                }
+                .leaf_id(),
             ],
             outputs: vec![Leaf {
                 version: 1,
+                nonce: 1,
                 owner: FixedBytes([3u8; 20]),
-                index_key: FixedBytes([4u8; 32]),
-                operator: Some(LeafId {
-                    txid: FixedBytes([5u8; 32]),
-                    index: 2,
-                }),
+                index: FixedBytes([4u8; 32]),
+                operator: Some(
+                    OutPoint {
+                        txid: FixedBytes([5u8; 32]),
+                        index: 2,
+                    }
+                    .leaf_id(),
+                ),
                 data: Bytes(vec![60, 70, 80, 90]),
             }],
         };
@@ -222,29 +330,40 @@ mod tests {
         let tx1 = UnsignedTransaction {
             version: 3,
             nonce: 54321,
-            inputs: vec![LeafId {
-                txid: FixedBytes([10u8; 32]),
-                index: 5,
-            }],
+            inputs: vec![
+                OutPoint {
+                    txid: FixedBytes([10u8; 32]),
+                    index: 5,
+                }
+                .leaf_id(),
+            ],
             outputs: vec![
                 Leaf {
                     version: 1,
+                    nonce: 1,
                     owner: FixedBytes([11u8; 20]),
-                    index_key: FixedBytes([12u8; 32]),
-                    operator: Some(LeafId {
-                        txid: FixedBytes([13u8; 32]),
-                        index: 10,
-                    }),
+                    index: FixedBytes([12u8; 32]),
+                    operator: Some(
+                        OutPoint {
+                            txid: FixedBytes([13u8; 32]),
+                            index: 10,
+                        }
+                        .leaf_id(),
+                    ),
                     data: Bytes(vec![100, 101, 102]),
                 },
                 Leaf {
                     version: 2,
+                    nonce: 2,
                     owner: FixedBytes([21u8; 20]),
-                    index_key: FixedBytes([22u8; 32]),
-                    operator: Some(LeafId {
-                        txid: FixedBytes([23u8; 32]),
-                        index: 20,
-                    }),
+                    index: FixedBytes([22u8; 32]),
+                    operator: Some(
+                        OutPoint {
+                            txid: FixedBytes([23u8; 32]),
+                            index: 20,
+                        }
+                        .leaf_id(),
+                    ),
                     data: Bytes(vec![200]),
                 },
             ],
@@ -258,4 +377,157 @@ mod tests {
             "Transaction with multiple outputs should be equal after serialization and deserialization"
         );
     }
+
+    #[test]
+    fn test_outputs_encoded_as_leaves() {
+        let outputs = vec![
+            Leaf {
+                version: 1,
+                nonce: 7,
+                owner: FixedBytes([1u8; 20]),
+                index: FixedBytes([2u8; 32]),
+                operator: None,
+                data: Bytes(vec![1, 2, 3]),
+            },
+            Leaf {
+                version: 2,
+                nonce: 8,
+                owner: FixedBytes([3u8; 20]),
+                index: FixedBytes([4u8; 32]),
+                operator: Some(FixedBytes([5u8; 32])),
+                data: Bytes(vec![]),
+            },
+        ];
+
+        let tx = UnsignedTransaction {
+            version: 1,
+            nonce: 1,
+            inputs: vec![FixedBytes([6u8; 32])],
+            outputs: outputs.clone(),
+        };
+
+        let mut leaves = Vec::new();
+        for output in &outputs {
+            output.append_to_vec(&mut leaves).unwrap();
+        }
+
+        let serialized = tx.to_vec().unwrap();
+        assert!(serialized.ends_with(&leaves));
+        assert_eq!(UnsignedTransaction::from_slice(&serialized).unwrap(), tx);
+    }
+
+    #[test]
+    fn test_transaction_truncated() {
+        let tx = UnsignedTransaction {
+            version: 1,
+            nonce: 1,
+            inputs: vec![FixedBytes([1u8; 32]), FixedBytes([2u8; 32])],
+            outputs: vec![Leaf {
+                version: 1,
+                nonce: 1,
+                owner: FixedBytes([3u8; 20]),
+                index: FixedBytes([4u8; 32]),
+                operator: None,
+                data: Bytes(vec![5, 6, 7]),
+            }],
+        };
+        let serialized = tx.to_vec().unwrap();
+
+        for len in 0..serialized.len() {
+            assert!(UnsignedTransaction::from_slice(&serialized[..len]).is_err());
+        }
+
+        // counts far past the end of the input
+        let mut corrupted = serialized.clone();
+        corrupted[9..17].copy_from_slice(&[0xff; 8]);
+        assert!(UnsignedTransaction::from_slice(&corrupted).is_err());
+    }
+
+    #[test]
+    fn test_transaction_strict() {
+        let tx = UnsignedTransaction {
+            version: 1,
+            nonce: 1,
+            inputs: vec![FixedBytes([1u8; 32])],
+            outputs: vec![Leaf {
+                version: 1,
+                nonce: 1,
+                owner: FixedBytes([2u8; 20]),
+                index: FixedBytes([3u8; 32]),
+                operator: None,
+                data: Bytes(vec![4, 5]),
+            }],
+        };
+        let serialized = tx.to_vec().unwrap();
+
+        let decoded = UnsignedTransaction::from_slice_strict(&serialized).unwrap();
+        assert_eq!(decoded.to_vec().unwrap(), serialized);
+
+        // lenient decoding ignores trailing bytes and the length table
+        let mut trailing = serialized.clone();
+        trailing.push(0);
+        assert_eq!(UnsignedTransaction::from_slice(&trailing).unwrap(), tx);
+        assert!(matches!(
+            UnsignedTransaction::from_slice_strict(&trailing),
+            Err(Error::TrailingBytes(1))
+        ));
+
+        let mut table = serialized.clone();
+        table[17..21].copy_from_slice(&7u32.to_be_bytes());
+        assert_eq!(UnsignedTransaction::from_slice(&table).unwrap(), tx);
+        assert!(matches!(
+            UnsignedTransaction::from_slice_strict(&table),
+            Err(Error::OutputLengthMismatch(0, 7, 2))
+        ));
+    }
+
+    #[test]
+    fn test_unsigned_transaction_parser() {
+        let tx = UnsignedTransaction {
+            version: 2,
+            nonce: 3,
+            inputs: vec![FixedBytes([1u8; 32]), FixedBytes([2u8; 32])],
+            outputs: vec![
+                Leaf {
+                    version: 1,
+                    nonce: 4,
+                    owner: FixedBytes([5u8; 20]),
+                    index: FixedBytes([6u8; 32]),
+                    operator: None,
+                    data: Bytes(vec![7, 8, 9]),
+                },
+                Leaf {
+                    version: 1,
+                    nonce: 5,
+                    owner: FixedBytes([10u8; 20]),
+                    index: FixedBytes([11u8; 32]),
+                    operator: Some(FixedBytes([12u8; 32])),
+                    data: Bytes(vec![]),
+                },
+            ],
+        };
+        let mut serialized = tx.to_vec().unwrap();
+        let tx_len = serialized.len();
+
+        // bytes after the transaction are left alone
+        serialized.extend_from_slice(&[0xff; 3]);
+
+        let parser = UnsignedTransactionParser::new(serialized.as_slice()).unwrap();
+        assert_eq!(parser.tx_len(), tx_len);
+        assert_eq!(parser.version(), 2);
+        assert_eq!(parser.nonce(), 3);
+        assert_eq!(parser.inputs_count(), 2);
+        assert_eq!(parser.outputs_count(), 2);
+
+        assert_eq!(parser.input(1), Some(&[2u8; 32]));
+        assert_eq!(parser.input(2), None);
+
+        let output = parser.output(0).unwrap();
+        assert_eq!(output.nonce(), 4);
+        assert_eq!(output.data(), &[7, 8, 9]);
+        assert_eq!(parser.output(1).unwrap().operator(), &[12u8; 32]);
+        assert!(parser.output(2).is_none());
+
+        assert_eq!(parser.to_unsigned_transaction().unwrap(), tx);
+    }
 }